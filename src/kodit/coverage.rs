@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use super::line::{Command, Line, LineItem};
+use super::trace::{TraceEvent, TraceHook};
+
+/// How many times a line ran, and for if and for lines how many times each way was taken.
+#[derive(Default)]
+struct LineCoverage {
+    executions: u64,
+    /// The times the condition of an if was true or a for loop was entered, and the times it wasn't.
+    branches: Option<(u64, u64)>,
+}
+
+/// Records which lines and branches of a program ran and writes them as an LCOV report when
+/// the program stops. Lines are keyed by their line number in the source file.
+pub struct Coverage {
+    file: String,
+    report: PathBuf,
+    lines: BTreeMap<usize, LineCoverage>,
+    /// The line of every function and how many times it was called.
+    functions: BTreeMap<String, (usize, u64)>,
+}
+
+impl Coverage {
+    /// Every line of the code is in the report, even the ones that never run.
+    pub fn new(file: &str, code: &[Line], report: PathBuf) -> Coverage {
+        let lines = code.iter().map(|line| {
+            let branches = match line.items.first() {
+                Some(LineItem::Command(Command::IF | Command::FOR)) => Some((0, 0)),
+                _ => None,
+            };
+            (line.line_number, LineCoverage { executions: 0, branches })
+        }).collect();
+
+        let functions = code.iter().filter_map(|line| match (line.items.first(), line.items.get(1)) {
+            (Some(LineItem::Command(Command::FUNCTION)), Some(LineItem::Label(name))) => Some((name.clone(), (line.line_number, 0))),
+            _ => None,
+        }).collect();
+
+        Coverage { file: file.to_string(), report, lines, functions }
+    }
+
+    fn lcov(&self) -> String {
+        let mut lcov = format!("TN:\nSF:{}\n", self.file);
+
+        for (name, (line_number, _)) in &self.functions {
+            lcov.push_str(&format!("FN:{},{}\n", line_number, name));
+        }
+        for (name, (_, calls)) in &self.functions {
+            lcov.push_str(&format!("FNDA:{},{}\n", calls, name));
+        }
+        lcov.push_str(&format!("FNF:{}\n", self.functions.len()));
+        lcov.push_str(&format!("FNH:{}\n", self.functions.values().filter(|(_, calls)| *calls > 0).count()));
+
+        for (line_number, line) in &self.lines {
+            if let Some((taken, not_taken)) = line.branches {
+                for (branch, count) in [taken, not_taken].iter().enumerate() {
+                    let count = if line.executions == 0 { "-".to_string() } else { count.to_string() };
+                    lcov.push_str(&format!("BRDA:{},0,{},{}\n", line_number, branch, count));
+                }
+            }
+        }
+
+        let branches: Vec<(u64, u64)> = self.lines.values().filter_map(|line| line.branches).collect();
+        lcov.push_str(&format!("BRF:{}\n", branches.len() * 2));
+        lcov.push_str(&format!("BRH:{}\n", branches.iter().map(|(taken, not_taken)| (*taken > 0) as usize + (*not_taken > 0) as usize).sum::<usize>()));
+
+        for (line_number, line) in &self.lines {
+            lcov.push_str(&format!("DA:{},{}\n", line_number, line.executions));
+        }
+
+        lcov.push_str(&format!("LF:{}\n", self.lines.len()));
+        lcov.push_str(&format!("LH:{}\n", self.lines.values().filter(|line| line.executions > 0).count()));
+        lcov.push_str("end_of_record\n");
+        lcov
+    }
+}
+
+impl TraceHook for Coverage {
+    fn trace(&mut self, event: &TraceEvent) {
+        let line = self.lines.entry(event.line_number).or_default();
+        line.executions += 1;
+
+        match (&mut line.branches, event.branch) {
+            (Some((taken, _)), Some(true)) => *taken += 1,
+            (Some((_, not_taken)), Some(false)) => *not_taken += 1,
+            _ => (),
+        }
+
+        // The function line runs once for every call, right after it.
+        if let (Command::FUNCTION, Some(function)) = (&event.command, event.functions.last()) {
+            match self.functions.get_mut(function) {
+                Some((_, calls)) if event.label.as_ref() == Some(function) => *calls += 1,
+                _ => (),
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Err(e) = fs::write(&self.report, self.lcov()) {
+            eprintln!("Cannot write {}: {}", self.report.display(), e);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use super::line::{Command, Line, LineItem};
+
+/// What the /// comments before a function line say about it.
+pub struct FunctionDoc {
+    pub name: String,
+    pub line_number: usize,
+    /// The function line as it is written in the source file.
+    pub signature: String,
+    pub description: Vec<String>,
+    /// Every parameter in order with its description, which is empty if it has none.
+    pub parameters: Vec<(String, String)>,
+    pub returns: Option<String>,
+}
+
+pub struct FileDoc {
+    pub file: String,
+    pub functions: Vec<FunctionDoc>,
+}
+
+/// Collects the functions of a lexed file in the order they are written. A comment line that
+/// starts with the name of a parameter and a colon describes the parameter, one that starts
+/// with returns: describes what the function returns, and the others describe the function.
+/// Lines after a parameter or return description continue it until the next one.
+pub fn document(file: &str, code: &[Line]) -> FileDoc {
+    let functions = code.iter().filter_map(|line| {
+        let name = match (line.items.first(), line.items.get(1)) {
+            (Some(LineItem::Command(Command::FUNCTION)), Some(LineItem::Label(name))) => name.clone(),
+            _ => return None,
+        };
+
+        let mut parameters: Vec<(String, String)> = line.items[2..].iter().filter_map(|item| match item {
+            LineItem::Label(parameter) => Some((parameter.clone(), String::new())),
+            _ => None,
+        }).collect();
+
+        let mut description = vec!();
+        let mut returns: Option<String> = None;
+        // Where the comment lines that aren't tagged go, by the index of the parameter or the return.
+        let mut continuing: Option<Option<usize>> = None;
+
+        for comment in &line.doc {
+            let tag = comment.split_once(':').map(|(tag, text)| (tag.trim(), text.trim()));
+
+            match tag {
+                Some(("returns" | "return", text)) => {
+                    returns = Some(text.to_string());
+                    continuing = Some(None);
+                },
+                Some((tag, text)) if parameters.iter().any(|(parameter, _)| parameter == tag) => {
+                    let index = parameters.iter().position(|(parameter, _)| parameter == tag).unwrap();
+                    parameters[index].1 = text.to_string();
+                    continuing = Some(Some(index));
+                },
+                _ => {
+                    let continued = match continuing {
+                        Some(Some(index)) => &mut parameters[index].1,
+                        Some(None) => returns.get_or_insert_with(String::new),
+                        None => {
+                            description.push(comment.clone());
+                            continue;
+                        },
+                    };
+                    if !continued.is_empty() {
+                        continued.push(' ');
+                    }
+                    continued.push_str(comment.trim());
+                },
+            }
+        }
+
+        Some(FunctionDoc {
+            name,
+            line_number: line.line_number,
+            signature: line.source.split_whitespace().collect::<Vec<&str>>().join(" "),
+            description,
+            parameters,
+            returns,
+        })
+    }).collect();
+
+    FileDoc { file: file.to_string(), functions }
+}
+
+pub fn markdown(files: &[FileDoc]) -> String {
+    let mut page = String::new();
+
+    for file in files {
+        page.push_str(&format!("# {}\n\n", file.file));
+
+        if file.functions.is_empty() {
+            page.push_str("There are no functions in this file.\n\n");
+        }
+
+        for function in &file.functions {
+            page.push_str(&format!("## {}\n\n", function.name));
+            page.push_str(&format!("```\n{}\n```\n\n", function.signature));
+            page.push_str(&format!("Defined at line {}.\n\n", function.line_number));
+
+            if !function.description.is_empty() {
+                page.push_str(&format!("{}\n\n", function.description.join("\n").trim()));
+            }
+
+            if !function.parameters.is_empty() {
+                page.push_str("Parameters:\n\n");
+                for (parameter, text) in &function.parameters {
+                    if text.is_empty() {
+                        page.push_str(&format!("- `{}`\n", parameter));
+                    } else {
+                        page.push_str(&format!("- `{}`: {}\n", parameter, text));
+                    }
+                }
+                page.push('\n');
+            }
+
+            if let Some(returns) = &function.returns {
+                page.push_str(&format!("Returns: {}\n\n", returns));
+            }
+        }
+    }
+
+    page
+}
+
+pub fn html(files: &[FileDoc]) -> String {
+    let mut page = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Kodit functions</title>\n</head>\n<body>\n");
+
+    for file in files {
+        page.push_str(&format!("<h1>{}</h1>\n", escape(&file.file)));
+
+        if file.functions.is_empty() {
+            page.push_str("<p>There are no functions in this file.</p>\n");
+        }
+
+        for function in &file.functions {
+            page.push_str(&format!("<h2 id=\"{}\">{}</h2>\n", escape(&function.name), escape(&function.name)));
+            page.push_str(&format!("<pre><code>{}</code></pre>\n", escape(&function.signature)));
+            page.push_str(&format!("<p>Defined at line {}.</p>\n", function.line_number));
+
+            // Empty comment lines separate paragraphs.
+            for paragraph in function.description.split(|comment| comment.trim().is_empty()).filter(|lines| !lines.is_empty()) {
+                page.push_str(&format!("<p>{}</p>\n", escape(&paragraph.join("\n"))));
+            }
+
+            if !function.parameters.is_empty() {
+                page.push_str("<p>Parameters:</p>\n<dl>\n");
+                for (parameter, text) in &function.parameters {
+                    page.push_str(&format!("<dt><code>{}</code></dt>\n", escape(parameter)));
+                    if !text.is_empty() {
+                        page.push_str(&format!("<dd>{}</dd>\n", escape(text)));
+                    }
+                }
+                page.push_str("</dl>\n");
+            }
+
+            if let Some(returns) = &function.returns {
+                page.push_str(&format!("<p>Returns: {}</p>\n", escape(returns)));
+            }
+        }
+    }
+
+    page.push_str("</body>\n</html>\n");
+    page
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
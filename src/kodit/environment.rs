@@ -4,7 +4,11 @@ use super::value::Value;
 
 pub struct Environment {
     pub return_address: Option<Label>,
+    /// The name of the function the environment was made for by a call, if any.
+    pub function: Option<String>,
     pub variables: HashMap<String, Value>,
+    /// The position of the next item of every running each loop by the index of its line.
+    pub iterators: HashMap<usize, usize>,
 }
 
 pub struct Label {
@@ -16,7 +20,9 @@ impl Environment {
     pub fn that_returns_to(ret : Option<Label>) -> Environment {
         Environment {
             return_address: ret,
-            variables: HashMap::new()
+            function: None,
+            variables: HashMap::new(),
+            iterators: HashMap::new(),
         }
     }
 }
\ No newline at end of file
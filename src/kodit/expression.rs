@@ -0,0 +1,188 @@
+use num_bigint::BigInt;
+
+use super::value::Value;
+use super::vm::VM;
+
+/// The operators of the sum command, from the lowest to the highest precedence.
+static OPERATORS: [(&str, u8); 10] = [
+    ("==", 0), ("<", 0), (">", 0), ("<=", 0), (">=", 0),
+    ("+", 1), ("-", 1),
+    ("*", 2), ("/", 2), ("%", 2),
+];
+
+enum Node {
+    Constant(Value),
+    Parameter(usize),
+    Operation(String, Box<Node>, Box<Node>),
+}
+
+/// An expression parsed once and evaluated with different arguments. Parameters are bound
+/// to the arguments in the order they first appear in the expression.
+pub struct Expression {
+    root: Node,
+    parameters: Vec<String>,
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+    parameters: Vec<String>,
+}
+
+fn precedence(token: &str) -> Option<u8> {
+    OPERATORS.iter().find(|(operator, _)| *operator == token).map(|(_, precedence)| *precedence)
+}
+
+impl Expression {
+    /// Expressions starting with an operator are read as prefix, ones ending with an operator
+    /// as postfix, and anything else as infix where parentheses can be used.
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let tokens: Vec<String> = source.replace('(', " ( ").replace(')', " ) ")
+            .split_whitespace()
+            .map(|token| token.to_string())
+            .collect();
+
+        let (first, last) = match (tokens.first(), tokens.last()) {
+            (Some(first), Some(last)) => (first.clone(), last.clone()),
+            _ => return Err("The expression is empty.".to_string()),
+        };
+
+        let mut parser = Parser { tokens, position: 0, parameters: vec!() };
+
+        let root = if precedence(&first).is_some() {
+            parser.prefix()?
+        } else if precedence(&last).is_some() {
+            parser.postfix()?
+        } else {
+            parser.infix(0)?
+        };
+
+        if let Some(token) = parser.tokens.get(parser.position) {
+            return Err(format!("Unexpected {} in the expression.", token));
+        }
+
+        Ok(Expression { root, parameters: parser.parameters })
+    }
+
+    pub fn evaluate(&self, arguments: &[Value]) -> Result<Value, String> {
+        if arguments.len() != self.parameters.len() {
+            return Err(format!("The expression has {} parameters ({}) but {} arguments were given.",
+                self.parameters.len(), self.parameters.join(", "), arguments.len()));
+        }
+
+        Expression::evaluate_node(&self.root, arguments)
+    }
+
+    fn evaluate_node(node: &Node, arguments: &[Value]) -> Result<Value, String> {
+        match node {
+            Node::Constant(value) => Ok(value.clone()),
+            Node::Parameter(index) => Ok(arguments[*index].clone()),
+            Node::Operation(operator, left, right) => {
+                let left = Expression::evaluate_node(left, arguments)?;
+                let right = Expression::evaluate_node(right, arguments)?;
+                Ok(VM::do_sum(&left, &right, operator)?)
+            },
+        }
+    }
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.position).ok_or("The expression ends too early.")?.clone();
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn operand(&mut self, token: String) -> Result<Node, String> {
+        if token == "(" || token == ")" {
+            return Err("Parentheses can only be used in infix expressions.".to_string());
+        }
+
+        let digits = token.strip_prefix(['-', '+']).unwrap_or(&token);
+        if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(Node::Constant(Value::integer_value(token.parse::<BigInt>().unwrap())));
+        }
+
+        if let Ok(number) = token.parse::<f64>() {
+            return Ok(Node::Constant(Value::number_value(number)));
+        }
+
+        let index = match self.parameters.iter().position(|parameter| *parameter == token) {
+            Some(index) => index,
+            None => {
+                self.parameters.push(token);
+                self.parameters.len() - 1
+            },
+        };
+
+        Ok(Node::Parameter(index))
+    }
+
+    fn prefix(&mut self) -> Result<Node, String> {
+        let token = self.next()?;
+
+        if precedence(&token).is_some() {
+            let left = self.prefix()?;
+            let right = self.prefix()?;
+            Ok(Node::Operation(token, Box::new(left), Box::new(right)))
+        } else {
+            self.operand(token)
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Node, String> {
+        let mut stack = vec!();
+
+        while self.position < self.tokens.len() {
+            let token = self.next()?;
+
+            if precedence(&token).is_some() {
+                let (right, left) = match (stack.pop(), stack.pop()) {
+                    (Some(right), Some(left)) => (right, left),
+                    _ => return Err(format!("{} needs two operands before it.", token)),
+                };
+                stack.push(Node::Operation(token, Box::new(left), Box::new(right)));
+            } else {
+                stack.push(self.operand(token)?);
+            }
+        }
+
+        match (stack.pop(), stack.is_empty()) {
+            (Some(root), true) => Ok(root),
+            _ => Err("The expression has operands without an operator.".to_string()),
+        }
+    }
+
+    /// Operators of the same precedence are grouped from the left.
+    fn infix(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut left = self.infix_operand()?;
+
+        while let Some(operator) = self.tokens.get(self.position).cloned() {
+            match precedence(&operator) {
+                Some(precedence) if precedence >= min_precedence => {
+                    self.position += 1;
+                    let right = self.infix(precedence + 1)?;
+                    left = Node::Operation(operator, Box::new(left), Box::new(right));
+                },
+                _ => break,
+            }
+        }
+
+        Ok(left)
+    }
+
+    fn infix_operand(&mut self) -> Result<Node, String> {
+        let token = self.next()?;
+
+        if token != "(" {
+            return self.operand(token);
+        }
+
+        let inner = self.infix(0)?;
+
+        match self.next() {
+            Ok(token) if token == ")" => Ok(inner),
+            _ => Err("A parenthesis is not closed.".to_string()),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// What the file command is allowed to touch.
+pub struct FileSystemPolicy {
+    pub enabled: bool,
+    pub read_only: bool,
+    /// Directories files must be inside of, or anywhere if there are none.
+    pub allowed_directories: Vec<PathBuf>,
+    /// The directory relative paths start from, or the working directory if there is none.
+    pub base_directory: Option<PathBuf>,
+}
+
+impl FileSystemPolicy {
+    pub fn disabled() -> FileSystemPolicy {
+        FileSystemPolicy {
+            enabled: false,
+            read_only: true,
+            allowed_directories: vec!(),
+            base_directory: None,
+        }
+    }
+
+    /// Reading and writing files inside of the directories only.
+    pub fn restricted_to(directories: Vec<PathBuf>) -> FileSystemPolicy {
+        FileSystemPolicy {
+            enabled: true,
+            read_only: false,
+            allowed_directories: directories,
+            base_directory: None,
+        }
+    }
+
+    pub fn unrestricted() -> FileSystemPolicy {
+        FileSystemPolicy {
+            enabled: true,
+            read_only: false,
+            allowed_directories: vec!(),
+            base_directory: None,
+        }
+    }
+
+    pub fn read(&self, path: &str) -> Result<String, String> {
+        let path = self.check(path, false)?;
+        fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))
+    }
+
+    pub fn read_lines(&self, path: &str) -> Result<Vec<String>, String> {
+        Ok(self.read(path)?.lines().map(|line| line.to_string()).collect())
+    }
+
+    pub fn write(&self, path: &str, content: &str, append: bool) -> Result<(), String> {
+        let path = self.check(path, true)?;
+        let mut file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(&path)
+            .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        file.write_all(content.as_bytes()).map_err(|e| format!("Cannot write to {}: {}", path.display(), e))
+    }
+
+    /// The names of the entries of a directory in alphabetical order.
+    pub fn list(&self, path: &str) -> Result<Vec<String>, String> {
+        let path = self.check(path, false)?;
+        let mut names = fs::read_dir(&path).map_err(|e| format!("Cannot list {}: {}", path.display(), e))?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| format!("Cannot list {}: {}", path.display(), e))?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Paths in directories that don't exist don't exist either, even if they lead back out of them.
+    pub fn exists(&self, path: &str) -> Result<bool, String> {
+        self.check_enabled(false)?;
+        match resolve(&self.locate(path)) {
+            Some(resolved) => Ok(self.check_allowed(path, &resolved)?.exists()),
+            None => Ok(false),
+        }
+    }
+
+    fn locate(&self, path: &str) -> PathBuf {
+        match &self.base_directory {
+            Some(base) if Path::new(path).is_relative() => base.join(path),
+            _ => PathBuf::from(path),
+        }
+    }
+
+    fn check_enabled(&self, write: bool) -> Result<(), String> {
+        if !self.enabled {
+            return Err("File access is disabled.".to_string());
+        }
+
+        if write && self.read_only {
+            return Err("File access is read-only.".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, path: &str, write: bool) -> Result<PathBuf, String> {
+        self.check_enabled(write)?;
+
+        let resolved = resolve(&self.locate(path)).ok_or_else(|| format!("Cannot find {}.", path))?;
+        self.check_allowed(path, &resolved)
+    }
+
+    fn check_allowed(&self, path: &str, resolved: &Path) -> Result<PathBuf, String> {
+        if self.allowed_directories.is_empty() {
+            return Ok(resolved.to_path_buf());
+        }
+
+        let allowed = self.allowed_directories.iter()
+            .filter_map(|directory| directory.canonicalize().ok())
+            .any(|directory| resolved.starts_with(directory));
+
+        if allowed {
+            Ok(resolved.to_path_buf())
+        } else {
+            Err(format!("{} is outside of the allowed directories.", path))
+        }
+    }
+}
+
+/// Resolves links and ".." so a path can't leave the allowed directories. Files that
+/// don't exist yet are resolved through their directory.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    if let Ok(resolved) = path.canonicalize() {
+        return Some(resolved);
+    }
+
+    let name = path.file_name()?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let resolved = parent.canonicalize().ok()?.join(name);
+
+    // Something is there but couldn't be resolved, like a link to a file that doesn't exist.
+    if resolved.symlink_metadata().is_ok() {
+        return None;
+    }
+
+    Some(resolved)
+}
@@ -0,0 +1,73 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+/// Where the say and ask commands write and read.
+pub trait IoBackend {
+    fn write(&mut self, text: &str);
+
+    /// The next line of input without its line ending, or None if there is none yet so
+    /// the program has to wait for the host to give one.
+    fn read_line(&mut self) -> Option<String>;
+
+    fn flush(&mut self) {}
+}
+
+/// The standard output and input of the process. Reading waits until a line is typed.
+pub struct StdIo;
+
+impl IoBackend for StdIo {
+    fn write(&mut self, text: &str) {
+        print!("{}", text);
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let mut buf = String::new();
+        io::stdin().lock().read_line(&mut buf).unwrap();
+
+        // pop the trailing newline in the string.
+        let trimmed = buf.strip_suffix("\r\n")
+            .or(buf.strip_suffix("\n"))
+            .unwrap_or(buf.as_str());
+
+        Some(trimmed.to_string())
+    }
+
+    fn flush(&mut self) {
+        io::stdout().flush().unwrap();
+    }
+}
+
+/// Input and output kept in memory. Clones share the same buffers, so the host can keep one
+/// to give input and take output while the VM owns another.
+#[derive(Clone, Default)]
+pub struct MemoryIo {
+    input: Rc<RefCell<VecDeque<String>>>,
+    output: Rc<RefCell<String>>,
+}
+
+impl MemoryIo {
+    pub fn new() -> MemoryIo {
+        MemoryIo::default()
+    }
+
+    pub fn push_input(&self, line: &str) {
+        self.input.borrow_mut().push_back(line.to_string());
+    }
+
+    /// Everything written since the last time the output was taken.
+    pub fn take_output(&self) -> String {
+        std::mem::take(&mut *self.output.borrow_mut())
+    }
+}
+
+impl IoBackend for MemoryIo {
+    fn write(&mut self, text: &str) {
+        self.output.borrow_mut().push_str(text);
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        self.input.borrow_mut().pop_front()
+    }
+}
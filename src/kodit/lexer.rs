@@ -1,5 +1,8 @@
-use super::line::Line;
-use super::lexing_specification::LexingSpecification;
+use super::line::{self, Line, LineItem};
+use super::lexing_specification::{LexingSpecification, Matcher};
+
+/// How many of the closest formats are listed when a line can't be lexed.
+const SUGGESTION_COUNT: usize = 3;
 
 pub fn lex_line(line : &Line, specs : &[Box<dyn LexingSpecification>]) -> Result<Line, String> {
     for spec in specs {
@@ -9,9 +12,112 @@ pub fn lex_line(line : &Line, specs : &[Box<dyn LexingSpecification>]) -> Result
         }
     }
 
-    return Err(format!("Couldn't lex line {:?}", line));
+    return Err(describe_lex_error(line, specs));
 }
 
 pub fn lex(lines : &[Line], specs : &[Box<dyn LexingSpecification>]) -> Result<Vec<Line>, String> {
     lines.iter().map(|line| lex_line(line, specs)).collect()
-}
\ No newline at end of file
+}
+
+/// Reads a program from a file and lexes it.
+pub fn lex_file(file_name : &str, specs : &[Box<dyn LexingSpecification>]) -> Result<Vec<Line>, String> {
+    let source = std::fs::read_to_string(file_name).map_err(|e| format!("Cannot read {}: {}", file_name, e))?;
+    let lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+
+    // We don't remove empty lines because possible debugging would require the exact line number.
+    let raw_lines = line::decompose_lines(&lines)?;
+
+    lex(&raw_lines, specs)
+}
+
+struct Suggestion<'a> {
+    spec_name: &'a str,
+    command_name: &'static str,
+    matchers: &'a [Matcher],
+    score: usize,
+}
+
+fn describe_lex_error(line : &Line, specs : &[Box<dyn LexingSpecification>]) -> String {
+    let mut message = format!("Couldn't lex line {}: {}", line.line_number, line.source.trim());
+
+    let mut suggestions: Vec<Suggestion> = specs.iter()
+        .flat_map(|spec| spec.matchings().iter().map(|(command, matchers)| Suggestion {
+            spec_name: spec.name(),
+            command_name: command.name(),
+            matchers,
+            score: score_format(&line.items, matchers),
+        }))
+        .collect();
+
+    // The sort is stable so formats that score the same keep the order they are tried in.
+    suggestions.sort_by_key(|suggestion| suggestion.score);
+
+    if suggestions.is_empty() {
+        return message;
+    }
+
+    message.push_str("\nClosest formats:");
+    for suggestion in suggestions.iter().take(SUGGESTION_COUNT) {
+        let format = suggestion.matchers.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(" ");
+        message.push_str(&format!("\n    {} ({}): {}", suggestion.command_name, suggestion.spec_name, format));
+    }
+
+    for (matcher, item) in suggestions[0].matchers.iter().zip(&line.items) {
+        if let (Matcher::Symbol(symbol), LineItem::Label(word)) = (matcher, item) {
+            let distance = edit_distance(symbol, word);
+            if distance > 0 && distance <= symbol.chars().count() / 2 {
+                message.push_str(&format!("\nDid you mean {} instead of {}?", symbol, word));
+            }
+        }
+    }
+
+    message
+}
+
+/// Lower is closer. Keywords are compared by edit distance to the item at the same position,
+/// and every missing or extra argument counts as one edit.
+fn score_format(items : &[LineItem], matchers : &[Matcher]) -> usize {
+    let rest = matchers.iter().position(|m| matches!(m, Matcher::Rest));
+    let required = &matchers[..rest.unwrap_or(matchers.len())];
+
+    let keyword_distance: usize = required.iter().enumerate().map(|(i, matcher)| match (matcher, items.get(i)) {
+        (Matcher::Symbol(symbol), Some(LineItem::Label(word))) => edit_distance(symbol, word),
+        (Matcher::Symbol(symbol), _) => symbol.chars().count(),
+        _ => 0,
+    }).sum();
+
+    let count_distance = if rest.is_some() {
+        required.len().saturating_sub(items.len())
+    } else {
+        required.len().abs_diff(items.len())
+    };
+
+    keyword_distance + count_distance
+}
+
+/// Optimal string alignment distance, so swapped letters count as a single edit.
+fn edit_distance(a : &str, b : &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in distances.iter_mut().enumerate() { row[0] = i; }
+    for (j, cell) in distances[0].iter_mut().enumerate() { *cell = j; }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
@@ -1,6 +1,21 @@
-use super::line::Line;
+use std::fmt::Display;
+
+use super::line::{Command, Line};
 
 pub mod v0;
+pub mod validation;
+
+/// Tried after the ones that are given, so programs can always use the English commands.
+pub const DEFAULT: &str = "lexing-specifications/en.yml";
+
+/// Loads the lexing specifications from the files in order, followed by the default one.
+pub fn load(file_names: &[String]) -> Result<Vec<Box<dyn LexingSpecification>>, String> {
+    file_names.iter()
+        .map(|file_name| file_name.as_str())
+        .chain(std::iter::once(DEFAULT))
+        .map(|file_name| v0::LexingSpecificationV0::from_file(file_name).map(|spec| Box::new(spec) as Box<dyn LexingSpecification>))
+        .collect()
+}
 
 pub enum Matcher {
     Symbol(String),
@@ -8,6 +23,21 @@ pub enum Matcher {
     Rest,
 }
 
+impl Display for Matcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Matcher::Symbol(symbol) => write!(f, "{}", symbol),
+            Matcher::Argument(index) => write!(f, "<{}>", index),
+            Matcher::Rest => write!(f, "..."),
+        }
+    }
+}
+
 pub trait LexingSpecification {
     fn lex(&self, line : &Line) -> Option<Line>;
-}
\ No newline at end of file
+
+    fn name(&self) -> &str;
+
+    /// The formats in the order they are tried while lexing.
+    fn matchings(&self) -> &[(Command, Vec<Matcher>)];
+}
@@ -29,7 +29,7 @@ impl LexingSpecification for LexingSpecificationV0 {
             match parse(&line.items, matchers, 0) {
                 Some(mut matching) => {
                     matching.arguments.sort_by(|m, n| m.0.cmp(&n.0));
-                    let mut line = Line::new(line.line_number);
+                    let mut line = Line { source: line.source.clone(), doc: line.doc.clone(), ..Line::new(line.line_number) };
                     line.items.push(LineItem::Command(command.to_owned()));
 
                     for (_, item) in matching.arguments {
@@ -43,6 +43,14 @@ impl LexingSpecification for LexingSpecificationV0 {
 
         return None;
     }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn matchings(&self) -> &[(Command, Vec<Matcher>)] {
+        &self.matchings
+    }
 }
 
 impl LexingSpecificationV0 {
@@ -0,0 +1,208 @@
+use std::fmt::Display;
+
+use crate::kodit::line::Command;
+use super::{LexingSpecification, Matcher};
+
+/// Two formats of the same specification that can match the same line.
+/// The first one is declared earlier and therefore always wins.
+pub struct Ambiguity {
+    pub first: Command,
+    pub second: Command,
+    pub example: String,
+}
+
+pub struct ValidationReport {
+    pub name: String,
+    pub missing_commands: Vec<Command>,
+    pub ambiguities: Vec<Ambiguity>,
+    pub argument_problems: Vec<String>,
+}
+
+impl ValidationReport {
+    /// Missing commands are not counted since they fall back to the next loaded specification.
+    pub fn has_problems(&self) -> bool {
+        !self.ambiguities.is_empty() || !self.argument_problems.is_empty()
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.name)?;
+
+        if self.missing_commands.is_empty() {
+            writeln!(f, "  All commands are covered.")?;
+        } else {
+            let names: Vec<&str> = self.missing_commands.iter().map(|c| c.name()).collect();
+            writeln!(f, "  Not covered (handled by the next specification): {}", names.join(", "))?;
+        }
+
+        for ambiguity in &self.ambiguities {
+            writeln!(f, "  Ambiguous: {} and {} both match \"{}\", {} wins because it comes first.",
+                ambiguity.first.name(), ambiguity.second.name(), ambiguity.example, ambiguity.first.name())?;
+        }
+
+        for problem in &self.argument_problems {
+            writeln!(f, "  Arguments: {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn validate(spec: &dyn LexingSpecification) -> ValidationReport {
+    let matchings = spec.matchings();
+
+    let missing_commands = Command::all().into_iter()
+        .filter(|command| !matchings.iter().any(|(c, _)| c == command))
+        .collect();
+
+    let mut ambiguities = vec!();
+
+    for (i, (first, first_matchers)) in matchings.iter().enumerate() {
+        for (second, second_matchers) in &matchings[i + 1..] {
+            if let Some(example) = find_overlap(first_matchers, second_matchers) {
+                ambiguities.push(Ambiguity { first: first.clone(), second: second.clone(), example });
+            }
+        }
+    }
+
+    let argument_problems = matchings.iter()
+        .flat_map(|(command, matchers)| check_arguments(command, matchers))
+        .collect();
+
+    ValidationReport {
+        name: spec.name().to_string(),
+        missing_commands,
+        ambiguities,
+        argument_problems,
+    }
+}
+
+/// Finds a line both formats would accept. The lexer ignores items left at the end of a line,
+/// so the first format also takes the lines of the second that start like it, as if it ended
+/// with "...". Lines the second format takes only by ignoring items are not considered, since
+/// the first format matches them completely and wins anyway.
+fn find_overlap(first: &[Matcher], second: &[Matcher]) -> Option<String> {
+    let rest = |matchers: &[Matcher]| matchers.iter().position(|m| matches!(m, Matcher::Rest));
+    let first_rest = rest(first);
+    let second_rest = rest(second);
+
+    let first = &first[..first_rest.unwrap_or(first.len())];
+    let second = &second[..second_rest.unwrap_or(second.len())];
+
+    let length = first.len().max(second.len());
+
+    if second_rest.is_none() && second.len() != length {
+        return None;
+    }
+
+    let mut example = vec!();
+
+    for i in 0..length {
+        let item = match (first.get(i), second.get(i)) {
+            (Some(Matcher::Symbol(a)), Some(Matcher::Symbol(b))) if a != b => return None,
+            (Some(Matcher::Symbol(a)), _) | (_, Some(Matcher::Symbol(a))) => a.to_owned(),
+            _ => "x".to_string(),
+        };
+        example.push(item);
+    }
+
+    Some(example.join(" "))
+}
+
+fn check_arguments(command: &Command, matchers: &[Matcher]) -> Vec<String> {
+    let format = matchers.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(" ");
+    let mut problems = vec!();
+
+    if let Some(position) = matchers.iter().position(|m| matches!(m, Matcher::Rest)) {
+        if position != matchers.len() - 1 {
+            problems.push(format!("{} \"{}\" has items after \"...\" which are never matched.", command.name(), format));
+        }
+    }
+
+    let mut indices: Vec<i32> = matchers.iter().filter_map(|m| match m {
+        Matcher::Argument(i) => Some(*i),
+        _ => None,
+    }).collect();
+    indices.sort();
+
+    for (expected, index) in indices.iter().enumerate() {
+        if expected > 0 && indices[expected - 1] == *index {
+            problems.push(format!("{} \"{}\" uses argument {} more than once.", command.name(), format, index));
+        }
+    }
+
+    indices.dedup();
+
+    if indices.iter().enumerate().any(|(expected, index)| expected as i32 != *index) {
+        problems.push(format!("{} \"{}\" should number its arguments 0 to {} without gaps.", command.name(), format, indices.len() as i32 - 1));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kodit::lexing_specification::v0::LexingSpecificationV0;
+
+    /// Numbers are arguments and ... is the rest, like in the specification files.
+    fn format(text: &str) -> Vec<Matcher> {
+        text.split_whitespace().map(|word| match word {
+            "..." => Matcher::Rest,
+            _ => match word.parse() {
+                Ok(index) => Matcher::Argument(index),
+                Err(_) => Matcher::Symbol(word.to_string()),
+            },
+        }).collect()
+    }
+
+    fn overlap(first: &str, second: &str) -> Option<String> {
+        find_overlap(&format(first), &format(second))
+    }
+
+    #[test]
+    fn different_symbols_do_not_overlap() {
+        assert_eq!(overlap("say 0", "ask 0"), None);
+        assert_eq!(overlap("0 1 hai", "0 1 yaz"), None);
+    }
+
+    #[test]
+    fn arguments_overlap_with_symbols() {
+        assert_eq!(overlap("0 1 hai", "sum 0 1"), Some("sum x hai".to_string()));
+        assert_eq!(overlap("table ...", "0 1 hai"), Some("table x hai".to_string()));
+    }
+
+    #[test]
+    fn an_earlier_format_takes_the_start_of_longer_lines() {
+        assert_eq!(overlap("0 etiketi", "0 etiketi git"), Some("x etiketi git".to_string()));
+        assert_eq!(overlap("forename 0", "forename 0 1"), Some("forename x x".to_string()));
+    }
+
+    #[test]
+    fn a_later_format_does_not_take_the_start_of_longer_lines() {
+        assert_eq!(overlap("forename 0 1", "forename 0"), None);
+        assert_eq!(overlap("0 etiketi git", "0 etiketi"), None);
+    }
+
+    #[test]
+    fn argument_numbers_must_be_unique_and_without_gaps() {
+        assert!(check_arguments(&Command::SET, &format("set 0 1")).is_empty());
+        assert_eq!(check_arguments(&Command::SET, &format("set 0 0")).len(), 1);
+        assert_eq!(check_arguments(&Command::SET, &format("set 0 2")).len(), 1);
+        assert_eq!(check_arguments(&Command::CALL, &format("call ... 0")).len(), 1);
+    }
+
+    #[test]
+    fn the_bundled_specifications_have_unambiguous_math_formats() {
+        for file in ["lexing-specifications/en.yml", "lexing-specifications/tr.yml", "lexing-specifications/delftish.yml"] {
+            let report = validate(&LexingSpecificationV0::from_file(file).unwrap());
+
+            assert!(report.argument_problems.is_empty(), "{}", report);
+            assert!(!report.ambiguities.iter().any(|a| a.first == Command::MATH || a.second == Command::MATH), "{}", report);
+        }
+
+        let english = validate(&LexingSpecificationV0::from_file(super::super::DEFAULT).unwrap());
+        assert!(english.missing_commands.is_empty() && english.ambiguities.is_empty(), "{}", english);
+    }
+}
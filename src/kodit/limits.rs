@@ -0,0 +1,14 @@
+use std::time::Duration;
+
+/// Caps on what a program can use so it can't run forever or take all the memory.
+/// Limits that are not set are not checked.
+#[derive(Clone, Default)]
+pub struct Limits {
+    /// Lines executed in total, counting every time a line runs.
+    pub max_lines: Option<u64>,
+    pub max_time: Option<Duration>,
+    /// Calls that haven't returned yet.
+    pub max_call_depth: Option<usize>,
+    /// Cells of all the tables that are alive at the same time.
+    pub max_table_cells: Option<usize>,
+}
@@ -1,9 +1,14 @@
+use num_bigint::BigInt;
 use snailquote;
 
 #[derive(Debug)]
 pub struct Line {
     pub line_number: usize,
     pub items: Vec<LineItem>,
+    /// The source text the line was decomposed from, spanning several lines for multiline tables.
+    pub source: String,
+    /// The text of the /// comment lines right before the line, without the slashes.
+    pub doc: Vec<String>,
 }
 
 impl Line {
@@ -11,6 +16,8 @@ impl Line {
         Self {
             line_number,
             items: vec!(),
+            source: String::new(),
+            doc: vec!(),
         }
     }
 }
@@ -21,11 +28,15 @@ pub enum LineItem {
     Label(String),
     String(String),
     Number(f64),
+    /// A number literal without a fraction or exponent.
+    Integer(BigInt),
     Array(Vec<LineItem>),
-    Table(Vec<usize>, Vec<LineItem>),
+    /// Dimensions and data. Dimensions can be number literals or variables and are evaluated at runtime.
+    Table(Vec<LineItem>, Vec<LineItem>),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Command {
     LABEL,
     FUNCTION,
@@ -35,6 +46,7 @@ pub enum Command {
     IF,
     FOR,
     CONTINUE,
+    EACH,
     SUM,
     SAY,
     ASK,
@@ -43,6 +55,33 @@ pub enum Command {
     GET,
     PUT,
     SLICE,
+    DIMENSIONS,
+    SIZE,
+    COPY,
+    DEEPCOPY,
+    FILL,
+    RESHAPE,
+    RESIZE,
+    DICTIONARY,
+    DELETE,
+    HAS,
+    KEYS,
+    LIST,
+    PUSH,
+    POP,
+    PUSHFRONT,
+    POPFRONT,
+    INSERT,
+    REMOVE,
+    SORT,
+    MATH,
+    RPN,
+    LET,
+    RANDOM,
+    EXIT,
+    ENV,
+    FILE,
+    ASSERT,
     NOOP,
 }
 
@@ -57,6 +96,7 @@ impl Command {
             "if" => Command::IF,
             "for" => Command::FOR,
             "continue" => Command::CONTINUE,
+            "each" => Command::EACH,
             "sum" => Command::SUM,
             "say" => Command::SAY,
             "ask" => Command::ASK,
@@ -65,14 +105,144 @@ impl Command {
             "get" => Command::GET,
             "put" => Command::PUT,
             "slice" => Command::SLICE,
+            "dimensions" => Command::DIMENSIONS,
+            "size" => Command::SIZE,
+            "copy" => Command::COPY,
+            "deepcopy" => Command::DEEPCOPY,
+            "fill" => Command::FILL,
+            "reshape" => Command::RESHAPE,
+            "resize" => Command::RESIZE,
+            "dictionary" => Command::DICTIONARY,
+            "delete" => Command::DELETE,
+            "has" => Command::HAS,
+            "keys" => Command::KEYS,
+            "list" => Command::LIST,
+            "push" => Command::PUSH,
+            "pop" => Command::POP,
+            "pushfront" => Command::PUSHFRONT,
+            "popfront" => Command::POPFRONT,
+            "insert" => Command::INSERT,
+            "remove" => Command::REMOVE,
+            "sort" => Command::SORT,
+            "math" => Command::MATH,
+            "rpn" => Command::RPN,
+            "let" => Command::LET,
+            "random" => Command::RANDOM,
+            "exit" => Command::EXIT,
+            "env" => Command::ENV,
+            "file" => Command::FILE,
+            "assert" => Command::ASSERT,
             _ => Command::NOOP,
         }
     }
+
+    /// The name used for the command in lexing specifications.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::LABEL => "label",
+            Command::FUNCTION => "function",
+            Command::CALL => "call",
+            Command::RETURN => "return",
+            Command::GOTO => "goto",
+            Command::IF => "if",
+            Command::FOR => "for",
+            Command::CONTINUE => "continue",
+            Command::EACH => "each",
+            Command::SUM => "sum",
+            Command::SAY => "say",
+            Command::ASK => "ask",
+            Command::SET => "set",
+            Command::TABLE => "table",
+            Command::GET => "get",
+            Command::PUT => "put",
+            Command::SLICE => "slice",
+            Command::DIMENSIONS => "dimensions",
+            Command::SIZE => "size",
+            Command::COPY => "copy",
+            Command::DEEPCOPY => "deepcopy",
+            Command::FILL => "fill",
+            Command::RESHAPE => "reshape",
+            Command::RESIZE => "resize",
+            Command::DICTIONARY => "dictionary",
+            Command::DELETE => "delete",
+            Command::HAS => "has",
+            Command::KEYS => "keys",
+            Command::LIST => "list",
+            Command::PUSH => "push",
+            Command::POP => "pop",
+            Command::PUSHFRONT => "pushfront",
+            Command::POPFRONT => "popfront",
+            Command::INSERT => "insert",
+            Command::REMOVE => "remove",
+            Command::SORT => "sort",
+            Command::MATH => "math",
+            Command::RPN => "rpn",
+            Command::LET => "let",
+            Command::RANDOM => "random",
+            Command::EXIT => "exit",
+            Command::ENV => "env",
+            Command::FILE => "file",
+            Command::ASSERT => "assert",
+            Command::NOOP => "noop",
+        }
+    }
+
+    /// Every command that can be given a format in a lexing specification.
+    pub fn all() -> Vec<Command> {
+        vec![
+            Command::LABEL,
+            Command::FUNCTION,
+            Command::CALL,
+            Command::RETURN,
+            Command::GOTO,
+            Command::IF,
+            Command::FOR,
+            Command::CONTINUE,
+            Command::EACH,
+            Command::SUM,
+            Command::SAY,
+            Command::ASK,
+            Command::SET,
+            Command::TABLE,
+            Command::GET,
+            Command::PUT,
+            Command::SLICE,
+            Command::DIMENSIONS,
+            Command::SIZE,
+            Command::COPY,
+            Command::DEEPCOPY,
+            Command::FILL,
+            Command::RESHAPE,
+            Command::RESIZE,
+            Command::DICTIONARY,
+            Command::DELETE,
+            Command::HAS,
+            Command::KEYS,
+            Command::LIST,
+            Command::PUSH,
+            Command::POP,
+            Command::PUSHFRONT,
+            Command::POPFRONT,
+            Command::INSERT,
+            Command::REMOVE,
+            Command::SORT,
+            Command::MATH,
+            Command::RPN,
+            Command::LET,
+            Command::RANDOM,
+            Command::EXIT,
+            Command::ENV,
+            Command::FILE,
+            Command::ASSERT,
+        ]
+    }
 }
 
 struct ParserState {
     pub current_table_depth: usize,
     pub current_line: Line,
+    /// Doc comments waiting for the next line with code.
+    pub doc: Vec<String>,
 }
 
 static SYMBOLS: [&'static str; 10] = ["+", "==", "-", "*", "/", "%", "<", ">", "<=", ">="];
@@ -81,17 +251,26 @@ pub fn decompose_lines(lines: &[String]) -> Result<Vec<Line>, String> {
     let mut parser_state = ParserState {
         current_table_depth: 0,
         current_line: Line::new(1),
+        doc: vec!(),
     };
 
     let mut produced_lines = vec!();
 
     for i in 0..lines.len() {
         decompose_line(&lines[i], &mut parser_state);
+
+        let source = &mut parser_state.current_line.source;
+        if !source.is_empty() { source.push('\n'); }
+        source.push_str(&lines[i]);
+
         if parser_state.current_table_depth == 0 {
             let raw_items = &parser_state.current_line.items;
-            if raw_items.is_empty() { continue; }
-            let items = arrays_to_tables(raw_items)?;
-            produced_lines.push(Line {line_number: parser_state.current_line.line_number, items });
+            if !raw_items.is_empty() {
+                let items = arrays_to_tables(raw_items)?;
+                let source = std::mem::take(&mut parser_state.current_line.source);
+                let doc = std::mem::take(&mut parser_state.doc);
+                produced_lines.push(Line {line_number: parser_state.current_line.line_number, items, source, doc });
+            }
             parser_state.current_line = Line::new(i + 2);
         }
     }
@@ -124,6 +303,11 @@ fn decompose_line(line: &String, parser_state: &mut ParserState) {
 
         // Comment
         if end - start >= 2 && &line[start..start + 2] == "//" {
+            // Doc comments on lines of their own are kept for the line after them.
+            if current_line[start..].starts_with("///") && parser_state.current_line.items.is_empty() {
+                let text = &current_line[start + 3..];
+                parser_state.doc.push(text.strip_prefix(' ').unwrap_or(text).trim_end().to_string());
+            }
             break;
         }
 
@@ -220,8 +404,12 @@ fn decompose_line(line: &String, parser_state: &mut ParserState) {
             } else {
                 let the_string = &current_line[start + skip_start..end - skip_end];
 
+                let digits = the_string.strip_prefix(['-', '+']).unwrap_or(the_string);
+
                 let line_item: LineItem = if SYMBOLS.contains(&the_string) {
                     LineItem::Label(the_string.to_string())
+                } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                    LineItem::Integer(the_string.parse().unwrap())
                 } else {
                     match the_string.parse() {
                         Ok(n) => LineItem::Number(n),
@@ -244,9 +432,9 @@ pub fn arrays_to_tables(line_items: &Vec<LineItem>) -> Result<Vec<LineItem>, Str
     while i < line_items.len() {
         result.push(match line_items.get(i).unwrap() {
             LineItem::Array(dims) => {
-                let dimensions: Vec<usize> = dims.iter().map(|v| match v {
-                    LineItem::Number(x) => Ok(x.round() as usize),
-                    _ => return Err("Table dimensions must be constant number literals.".to_string()),
+                let dimensions: Vec<LineItem> = dims.iter().map(|v| match v {
+                    LineItem::Number(_) | LineItem::Integer(_) | LineItem::Label(_) => Ok(v.clone()),
+                    _ => Err("Table dimensions must be number literals or variables.".to_string()),
                 }).collect::<Result<_, String>>()?;
 
                 i += 1;
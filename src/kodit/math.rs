@@ -0,0 +1,147 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+
+use super::value::{Value, ValueType};
+
+/// The most bits an integer power may have, about 300000 decimal digits.
+const MAX_POWER_BITS: u64 = 1 << 20;
+
+/// Evaluates a function of the math command. Rounding functions and integer division give
+/// integers, the others give integers only where the arguments are integers and the result is exact.
+pub fn evaluate(function: &str, arguments: &[Value]) -> Result<Value, String> {
+    if arguments.iter().any(|argument| !matches!(argument.value_type, ValueType::Number)) {
+        return Err(format!("The arguments of {} must be numbers.", function));
+    }
+
+    let expected = match function {
+        "pi" | "e" => 0,
+        "floor" | "ceil" | "round" | "trunc" | "abs" | "sign" | "sqrt" | "exp" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" => 1,
+        "pow" | "atan2" | "idiv" | "gcd" | "lcm" => 2,
+        "log" => if arguments.len() == 2 { 2 } else { 1 },
+        "min" | "max" => arguments.len().max(1),
+        _ => return Err(format!("Unknown math function {}.", function)),
+    };
+
+    if arguments.len() != expected {
+        return Err(format!("{} takes {} argument{} but {} were given.", function, expected, if expected == 1 { "" } else { "s" }, arguments.len()));
+    }
+
+    let x = arguments.first().map(|a| a.number_value).unwrap_or(0.0);
+    let y = arguments.get(1).map(|a| a.number_value).unwrap_or(0.0);
+
+    match function {
+        "pi" => Ok(Value::number_value(std::f64::consts::PI)),
+        "e" => Ok(Value::number_value(std::f64::consts::E)),
+        "floor" => round_with(&arguments[0], f64::floor),
+        "ceil" => round_with(&arguments[0], f64::ceil),
+        "round" => round_with(&arguments[0], f64::round),
+        "trunc" => round_with(&arguments[0], f64::trunc),
+        "abs" => Ok(match &arguments[0].integer_value {
+            Some(n) => Value::integer_value(n.abs()),
+            None => Value::number_value(x.abs()),
+        }),
+        "sign" => Ok(match &arguments[0].integer_value {
+            Some(n) => Value::integer_value(n.signum()),
+            None if x == 0.0 || x.is_nan() => Value::number_value(x),
+            None => Value::integer_value(BigInt::from(x.signum() as i32)),
+        }),
+        "min" | "max" => {
+            let wanted = if function == "min" { Ordering::Less } else { Ordering::Greater };
+            let mut best = &arguments[0];
+            for argument in &arguments[1..] {
+                match argument.compare_numbers(best) {
+                    Some(ordering) if ordering == wanted => best = argument,
+                    Some(_) => (),
+                    None => return Err(format!("{} can't compare NaN.", function)),
+                }
+            }
+            Ok(best.clone())
+        },
+        "pow" => match (&arguments[0].integer_value, &arguments[1].integer_value) {
+            (Some(base), Some(exponent)) if !exponent.is_negative() => match exponent.to_u32() {
+                // The result has at least this many bits, and working it out takes long past the cap.
+                Some(exponent) if (base.bits().saturating_sub(1)).saturating_mul(exponent as u64) > MAX_POWER_BITS => {
+                    Err("The result of pow is too large.".to_string())
+                },
+                Some(exponent) => Ok(Value::integer_value(base.pow(exponent))),
+                None => Err("The exponent is too large.".to_string()),
+            },
+            _ => defined(x.powf(y), "pow"),
+        },
+        "sqrt" => {
+            if x < 0.0 {
+                return Err("Cannot take the square root of a negative number.".to_string());
+            }
+            match &arguments[0].integer_value {
+                Some(n) if n.sqrt().pow(2) == *n => Ok(Value::integer_value(n.sqrt())),
+                _ => Ok(Value::number_value(x.sqrt())),
+            }
+        },
+        "log" => {
+            if x <= 0.0 {
+                return Err("Cannot take the logarithm of a number that isn't positive.".to_string());
+            }
+            if arguments.len() == 1 {
+                return Ok(Value::number_value(x.ln()));
+            }
+            if y <= 0.0 || y == 1.0 {
+                return Err("The base of a logarithm must be positive and not 1.".to_string());
+            }
+            Ok(Value::number_value(x.log(y)))
+        },
+        "exp" => defined(x.exp(), "exp"),
+        "sin" => Ok(Value::number_value(x.sin())),
+        "cos" => Ok(Value::number_value(x.cos())),
+        "tan" => Ok(Value::number_value(x.tan())),
+        "asin" | "acos" => {
+            if !(-1.0..=1.0).contains(&x) {
+                return Err(format!("{} is only defined between -1 and 1.", function));
+            }
+            Ok(Value::number_value(if function == "asin" { x.asin() } else { x.acos() }))
+        },
+        "atan" => Ok(Value::number_value(x.atan())),
+        "atan2" => Ok(Value::number_value(x.atan2(y))),
+        "idiv" => match (&arguments[0].integer_value, &arguments[1].integer_value) {
+            (_, Some(b)) if b.is_zero() => Err("Division by zero.".to_string()),
+            (Some(a), Some(b)) => Ok(Value::integer_value(a.div_floor(b))),
+            _ if y == 0.0 => Err("Division by zero.".to_string()),
+            _ => round_with(&Value::number_value(x / y), f64::floor),
+        },
+        "gcd" | "lcm" => {
+            let a = as_integer(&arguments[0], function)?;
+            let b = as_integer(&arguments[1], function)?;
+            Ok(Value::integer_value(if function == "gcd" { a.gcd(&b) } else { a.lcm(&b) }))
+        },
+        _ => Err(format!("Unknown math function {}.", function)),
+    }
+}
+
+fn round_with(value: &Value, round: fn(f64) -> f64) -> Result<Value, String> {
+    if value.integer_value.is_some() {
+        return Ok(value.clone());
+    }
+
+    match BigInt::from_f64(round(value.number_value)) {
+        Some(n) => Ok(Value::integer_value(n)),
+        None => Err("Cannot round a number that isn't finite.".to_string()),
+    }
+}
+
+fn defined(result: f64, function: &str) -> Result<Value, String> {
+    if result.is_nan() {
+        Err(format!("The result of {} is not a number.", function))
+    } else {
+        Ok(Value::number_value(result))
+    }
+}
+
+fn as_integer(value: &Value, function: &str) -> Result<BigInt, String> {
+    match &value.integer_value {
+        Some(n) => Ok(n.clone()),
+        None if value.number_value.fract() == 0.0 => BigInt::from_f64(value.number_value).ok_or_else(|| format!("The arguments of {} must be integers.", function)),
+        None => Err(format!("The arguments of {} must be integers.", function)),
+    }
+}
@@ -3,5 +3,17 @@ pub mod line;
 pub mod environment;
 pub mod value;
 pub mod table;
+pub mod math;
+pub mod expression;
+pub mod random;
+pub mod file_system;
+pub mod limits;
+pub mod io_backend;
+pub mod snapshot;
+pub mod trace;
+pub mod profiler;
+pub mod coverage;
+pub mod test_runner;
+pub mod doc;
 pub mod lexer;
 pub mod lexing_specification;
\ No newline at end of file
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::line::Command;
+use super::trace::{TraceEvent, TraceHook};
+
+/// How many lines the report lists, the slowest first.
+const HOTTEST_LINES: usize = 20;
+
+#[derive(Default)]
+struct LineProfile {
+    source: String,
+    executions: u64,
+    time: Duration,
+    tables_created: u64,
+}
+
+#[derive(Default)]
+struct FunctionProfile {
+    calls: u64,
+    /// Time spent in the function and the functions it called.
+    inclusive: Duration,
+    /// Time spent in the lines of the function itself.
+    exclusive: Duration,
+}
+
+/// Counts how often and how long lines and functions run, and writes a report when the program stops.
+pub struct Profiler {
+    report: Box<dyn Write>,
+    /// Where to write the time of every stack of calls in the folded format of flame graph tools.
+    folded_stacks: Option<PathBuf>,
+    lines: HashMap<(String, usize), LineProfile>,
+    functions: HashMap<String, FunctionProfile>,
+    stacks: HashMap<String, Duration>,
+    executions: u64,
+    time: Duration,
+    garbage_collection: Duration,
+    garbage_collection_pauses: u64,
+}
+
+impl Profiler {
+    pub fn new(report: Box<dyn Write>, folded_stacks: Option<PathBuf>) -> Profiler {
+        Profiler {
+            report,
+            folded_stacks,
+            lines: HashMap::new(),
+            functions: HashMap::new(),
+            stacks: HashMap::new(),
+            executions: 0,
+            time: Duration::ZERO,
+            garbage_collection: Duration::ZERO,
+            garbage_collection_pauses: 0,
+        }
+    }
+
+    fn write_report(&mut self) -> io::Result<()> {
+        writeln!(self.report, "Ran {} lines in {:.6}s. Garbage collection paused the program {} times for {:.6}s.",
+            self.executions, self.time.as_secs_f64(), self.garbage_collection_pauses, self.garbage_collection.as_secs_f64())?;
+
+        let mut lines: Vec<(&(String, usize), &LineProfile)> = self.lines.iter().collect();
+        lines.sort_by(|(a_line, a), (b_line, b)| b.time.cmp(&a.time).then(a_line.cmp(b_line)));
+
+        writeln!(self.report)?;
+        writeln!(self.report, "Hottest lines:")?;
+        writeln!(self.report, "{:>12} {:>7} {:>12} {:>8}  line", "time", "share", "executions", "tables")?;
+        for ((file, line_number), line) in lines.into_iter().take(HOTTEST_LINES) {
+            writeln!(self.report, "{:>11.6}s {:>6.1}% {:>12} {:>8}  {}:{} {}",
+                line.time.as_secs_f64(), share(line.time, self.time), line.executions, line.tables_created,
+                file, line_number, line.source.split_whitespace().collect::<Vec<&str>>().join(" "))?;
+        }
+
+        if self.functions.is_empty() {
+            return self.report.flush();
+        }
+
+        let mut functions: Vec<(&String, &FunctionProfile)> = self.functions.iter().collect();
+        functions.sort_by(|(a_name, a), (b_name, b)| b.inclusive.cmp(&a.inclusive).then(a_name.cmp(b_name)));
+
+        writeln!(self.report)?;
+        writeln!(self.report, "Functions:")?;
+        writeln!(self.report, "{:>12} {:>7} {:>12} {:>7} {:>10}  function", "inclusive", "share", "exclusive", "share", "calls")?;
+        for (name, function) in functions {
+            writeln!(self.report, "{:>11.6}s {:>6.1}% {:>11.6}s {:>6.1}% {:>10}  {}",
+                function.inclusive.as_secs_f64(), share(function.inclusive, self.time),
+                function.exclusive.as_secs_f64(), share(function.exclusive, self.time),
+                function.calls, name)?;
+        }
+
+        self.report.flush()
+    }
+
+    /// One line for every stack of calls with the microseconds spent in its innermost function.
+    fn folded(&self) -> String {
+        let mut stacks: Vec<(&String, &Duration)> = self.stacks.iter().collect();
+        stacks.sort();
+
+        stacks.into_iter()
+            .filter(|(_, time)| time.as_micros() > 0)
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_micros()))
+            .collect()
+    }
+}
+
+impl TraceHook for Profiler {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.executions += 1;
+        self.time += event.duration;
+
+        if event.garbage_collection > Duration::ZERO {
+            self.garbage_collection += event.garbage_collection;
+            self.garbage_collection_pauses += 1;
+        }
+
+        let line = self.lines.entry((event.file.clone(), event.line_number))
+            .or_insert_with(|| LineProfile { source: event.source.clone(), ..LineProfile::default() });
+        line.executions += 1;
+        line.time += event.duration;
+        line.tables_created += event.tables_created;
+
+        // A function that is in the stack more than once by recursion only counts once.
+        for (depth, function) in event.functions.iter().enumerate() {
+            if !event.functions[..depth].contains(function) {
+                self.functions.entry(function.clone()).or_default().inclusive += event.duration;
+            }
+        }
+
+        if let Some(innermost) = event.functions.last() {
+            let function = self.functions.entry(innermost.clone()).or_default();
+            function.exclusive += event.duration;
+
+            // The function line runs once for every call, right after it.
+            if matches!(event.command, Command::FUNCTION) && event.label.as_ref() == Some(innermost) {
+                function.calls += 1;
+            }
+        }
+
+        let stack = std::iter::once(&event.file).chain(&event.functions).cloned().collect::<Vec<String>>().join(";");
+        *self.stacks.entry(stack).or_default() += event.duration;
+    }
+
+    fn finish(&mut self) {
+        if let Err(e) = self.write_report() {
+            eprintln!("Cannot write the profile: {}", e);
+        }
+
+        if let Some(file_name) = &self.folded_stacks {
+            if let Err(e) = fs::write(file_name, self.folded()) {
+                eprintln!("Cannot write {}: {}", file_name.display(), e);
+            }
+        }
+    }
+}
+
+fn share(time: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        0.0
+    } else {
+        time.as_secs_f64() / total.as_secs_f64() * 100.0
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use num_bigint::BigUint;
+
+/// A xoshiro256** generator. It is implemented here rather than taken from a crate so the
+/// same seed gives the same numbers on every platform and version.
+pub struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+    /// The state is filled with splitmix64 so that similar seeds give unrelated sequences.
+    pub fn new(seed: u64) -> Random {
+        let mut seed = seed;
+        let mut next = || {
+            seed = seed.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+
+        Random { state: [next(), next(), next(), next()] }
+    }
+
+    pub fn from_time() -> Random {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        Random::new(nanos as u64)
+    }
+
+    /// Gives back a generator that continues from where another one was.
+    pub fn from_state(state: [u64; 4]) -> Random {
+        Random { state }
+    }
+
+    pub fn state(&self) -> [u64; 4] {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+
+        result
+    }
+
+    /// A float between 0 inclusive and 1 exclusive.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number below the bound, which must not be zero. Draws that would make
+    /// some numbers more likely than others are thrown away.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let threshold = bound.wrapping_neg() % bound;
+
+        loop {
+            let r = self.next_u64();
+            if r >= threshold {
+                return r % bound;
+            }
+        }
+    }
+
+    /// Like below but for bounds of any size.
+    pub fn below_big(&mut self, bound: &BigUint) -> BigUint {
+        let bits = bound.bits();
+        let words = bits.div_ceil(32) as usize;
+
+        loop {
+            let mut digits: Vec<u32> = (0..words).map(|_| self.next_u64() as u32).collect();
+            if let Some(top) = digits.last_mut() {
+                *top &= u32::MAX >> (words as u64 * 32 - bits);
+            }
+
+            let candidate = BigUint::new(digits);
+            if candidate < *bound {
+                return candidate;
+            }
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use num_bigint::BigInt;
+use yaml_rust::{yaml::{Hash, Yaml}, YamlEmitter, YamlLoader};
+
+use super::environment::{Environment, Label};
+use super::line::Line;
+use super::random::Random;
+use super::table::{TableManager, TableSlice};
+use super::value::{Value, ValueType};
+use super::vm::{Status, VM};
+
+/// Snapshots of other versions are refused since their layout may be different.
+pub const VERSION: i64 = 1;
+
+/// FNV-1a of the source of every line, so a snapshot is only resumed with the program it was taken from.
+pub fn checksum(code: &[Line]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for line in code {
+        for byte in line.source.bytes().chain(std::iter::once(b'\n')) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash
+}
+
+/// Writes everything needed to continue a paused program. The limits, the IO backend and what
+/// the program can access are not saved since the host sets them again when resuming.
+pub fn save(vm: &VM) -> Result<String, String> {
+    let status = match vm.status {
+        Status::Running => "running",
+        Status::WaitingForInput => "waiting_for_input",
+        _ => return Err("Only programs that haven't ended can be saved.".to_string()),
+    };
+
+    let mut labels: Vec<(&String, &Label)> = vm.labels.iter().collect();
+    labels.sort_by_key(|(_, label)| label.line_number);
+
+    let root = hash(vec!(
+        ("version", Yaml::Integer(VERSION)),
+        ("checksum", Yaml::String(format!("{:016x}", checksum(&vm.code)))),
+        ("file", Yaml::String(vm.file.clone())),
+        ("program_counter", integer(vm.program_counter)),
+        ("status", Yaml::String(status.to_string())),
+        ("executed_lines", Yaml::Integer(vm.executed_lines as i64)),
+        ("table_creations", Yaml::Integer(vm.table_creations as i64)),
+        ("random", Yaml::Array(vm.random.state().iter().map(|word| Yaml::Integer(*word as i64)).collect())),
+        ("labels", Yaml::Array(labels.into_iter().map(|(name, label)| hash(vec!(
+            ("name", Yaml::String(name.clone())),
+            ("file", Yaml::String(label.file.clone())),
+            ("line", integer(label.line_number)),
+        ))).collect())),
+        ("environments", Yaml::Array(vm.environment.iter().map(environment_to_yaml).collect())),
+        ("tables", vm.table_manager.to_yaml()),
+    ));
+
+    let mut text = String::new();
+    YamlEmitter::new(&mut text).dump(&root).map_err(|e| format!("Cannot write the snapshot: {}", e))?;
+    text.push('\n');
+    Ok(text)
+}
+
+/// Loads the program and puts it back in the state it was saved in. The code must be the same
+/// as when the snapshot was taken.
+pub fn restore(vm: &mut VM, file: &String, code: Vec<Line>, text: &str) -> Result<(), String> {
+    let documents = YamlLoader::load_from_str(text).map_err(|e| format!("Cannot read the snapshot: {}", e))?;
+    let root = documents.first().ok_or("The snapshot is empty.")?;
+
+    let version = field(root, "version")?.as_i64().ok_or("The snapshot version must be a number.")?;
+    if version != VERSION {
+        return Err(format!("The snapshot is of version {} but only version {} can be restored.", version, VERSION));
+    }
+
+    if string_of(root, "checksum")? != format!("{:016x}", checksum(&code)) {
+        return Err("The snapshot was taken from a different program.".to_string());
+    }
+
+    vm.load(file, code).map_err(|e| e.to_string())?;
+
+    vm.program_counter = usize_of(root, "program_counter")?;
+    if vm.program_counter >= vm.code.len() {
+        return Err("The program counter of the snapshot is past the end of the program.".to_string());
+    }
+
+    vm.status = match string_of(root, "status")? {
+        "running" => Status::Running,
+        "waiting_for_input" => Status::WaitingForInput,
+        status => return Err(format!("Unknown status {} in the snapshot.", status)),
+    };
+
+    vm.executed_lines = usize_of(root, "executed_lines")? as u64;
+    vm.table_creations = usize_of(root, "table_creations")? as u32;
+
+    let random = array_of(root, "random")?.iter()
+        .map(|word| word.as_i64().map(|word| word as u64))
+        .collect::<Option<Vec<u64>>>()
+        .and_then(|words| words.try_into().ok())
+        .ok_or("The random state must be four numbers.")?;
+    vm.random = Random::from_state(random);
+
+    for label in array_of(root, "labels")? {
+        vm.add_label(string_of(label, "name")?.to_string(), string_of(label, "file")?.to_string(), usize_of(label, "line")?);
+    }
+
+    vm.environment = array_of(root, "environments")?.iter().map(environment_from_yaml).collect::<Result<Vec<Environment>, String>>()?;
+    if vm.environment.is_empty() {
+        return Err("The snapshot has no environments.".to_string());
+    }
+
+    vm.table_manager = TableManager::from_yaml(field(root, "tables")?)?;
+
+    Ok(())
+}
+
+fn environment_to_yaml(environment: &Environment) -> Yaml {
+    let mut variables: Vec<(&String, &Value)> = environment.variables.iter().collect();
+    variables.sort_by_key(|(name, _)| *name);
+
+    let mut iterators: Vec<(&usize, &usize)> = environment.iterators.iter().collect();
+    iterators.sort();
+
+    hash(vec!(
+        ("return_address", match &environment.return_address {
+            Some(label) => hash(vec!(("file", Yaml::String(label.file.clone())), ("line", integer(label.line_number)))),
+            None => Yaml::Null,
+        }),
+        ("function", environment.function.clone().map(Yaml::String).unwrap_or(Yaml::Null)),
+        ("variables", Yaml::Hash(variables.into_iter().map(|(name, value)| (Yaml::String(name.clone()), value_to_yaml(value))).collect())),
+        ("iterators", Yaml::Array(iterators.into_iter().map(|(line, position)| Yaml::Array(vec!(integer(*line), integer(*position)))).collect())),
+    ))
+}
+
+fn environment_from_yaml(yaml: &Yaml) -> Result<Environment, String> {
+    let return_address = match field(yaml, "return_address")? {
+        Yaml::Null => None,
+        label => Some(Label { file: string_of(label, "file")?.to_string(), line_number: usize_of(label, "line")? }),
+    };
+
+    let mut environment = Environment::that_returns_to(return_address);
+    environment.function = yaml["function"].as_str().map(|function| function.to_string());
+
+    for (name, value) in field(yaml, "variables")?.as_hash().ok_or("Variables must be an object in snapshots.")? {
+        let name = name.as_str().ok_or("Variable names must be strings in snapshots.")?;
+        environment.variables.insert(name.to_string(), value_from_yaml(value)?);
+    }
+
+    for iterator in array_of(yaml, "iterators")? {
+        match usizes_from(iterator)?.as_slice() {
+            [line, position] => environment.iterators.insert(*line, *position),
+            _ => return Err("Iterators must be a line and a position in snapshots.".to_string()),
+        };
+    }
+
+    Ok(environment)
+}
+
+/// Values are objects with a single key naming their type. Tables, dictionaries and lists are
+/// saved by id, floats as text so they are restored exactly.
+pub fn value_to_yaml(value: &Value) -> Yaml {
+    match value.value_type {
+        ValueType::String => hash(vec!(("string", Yaml::String(value.string_value.clone().unwrap_or_default())))),
+        ValueType::Number => match &value.integer_value {
+            Some(n) => hash(vec!(("integer", Yaml::String(n.to_string())))),
+            None => hash(vec!(("number", Yaml::String(value.number_value.to_string())))),
+        },
+        ValueType::Table if value.is_uninitialized() => hash(vec!(("table", Yaml::Null))),
+        ValueType::Table => {
+            let mut pairs = vec!(("table", integer(value.table_index)));
+            if let Some(slice) = &value.slice {
+                pairs.push(("slice", hash(vec!(
+                    ("offset", usizes(&slice.offset)),
+                    ("extent", usizes(&slice.extent)),
+                    ("stride", usizes(&slice.stride)),
+                ))));
+            }
+            hash(pairs)
+        },
+        ValueType::Dictionary => hash(vec!(("dictionary", integer(value.table_index)))),
+        ValueType::List => hash(vec!(("list", integer(value.table_index)))),
+    }
+}
+
+pub fn value_from_yaml(yaml: &Yaml) -> Result<Value, String> {
+    if let Some(string) = yaml["string"].as_str() {
+        return Ok(Value::string_value(&string.to_string()));
+    }
+
+    if let Some(integer) = yaml["integer"].as_str() {
+        return integer.parse::<BigInt>().map(Value::integer_value).map_err(|_| format!("{} is not an integer.", integer));
+    }
+
+    if let Some(number) = yaml["number"].as_str() {
+        return number.parse::<f64>().map(Value::number_value).map_err(|_| format!("{} is not a number.", number));
+    }
+
+    let (value_type, id) = match (&yaml["table"], &yaml["dictionary"], &yaml["list"]) {
+        (Yaml::Null, _, _) => return Ok(Value::uninitialized()),
+        (id, _, _) if !id.is_badvalue() => (ValueType::Table, id),
+        (_, id, _) if !id.is_badvalue() => (ValueType::Dictionary, id),
+        (_, _, id) if !id.is_badvalue() => (ValueType::List, id),
+        _ => return Err("Unknown value in the snapshot.".to_string()),
+    };
+
+    let slice = match &yaml["slice"] {
+        slice if slice.is_badvalue() => None,
+        slice => Some(TableSlice {
+            offset: usizes_from(field(slice, "offset")?)?,
+            extent: usizes_from(field(slice, "extent")?)?,
+            stride: usizes_from(field(slice, "stride")?)?,
+        }),
+    };
+
+    Ok(Value {
+        value_type,
+        string_value: None,
+        number_value: 0f64,
+        integer_value: None,
+        table_index: usize_from(id)?,
+        slice,
+    })
+}
+
+pub fn hash(pairs: Vec<(&str, Yaml)>) -> Yaml {
+    Yaml::Hash(pairs.into_iter().map(|(key, value)| (Yaml::String(key.to_string()), value)).collect::<Hash>())
+}
+
+pub fn integer(n: usize) -> Yaml {
+    Yaml::Integer(n as i64)
+}
+
+pub fn usizes(numbers: &[usize]) -> Yaml {
+    Yaml::Array(numbers.iter().map(|n| integer(*n)).collect())
+}
+
+pub fn field<'a>(yaml: &'a Yaml, name: &str) -> Result<&'a Yaml, String> {
+    match &yaml[name] {
+        value if value.is_badvalue() => Err(format!("The snapshot is missing {}.", name)),
+        value => Ok(value),
+    }
+}
+
+pub fn string_of<'a>(yaml: &'a Yaml, name: &str) -> Result<&'a str, String> {
+    field(yaml, name)?.as_str().ok_or_else(|| format!("{} must be a string in snapshots.", name))
+}
+
+pub fn usize_of(yaml: &Yaml, name: &str) -> Result<usize, String> {
+    usize_from(field(yaml, name)?).map_err(|_| format!("{} must be a number that isn't negative in snapshots.", name))
+}
+
+pub fn array_of<'a>(yaml: &'a Yaml, name: &str) -> Result<&'a Vec<Yaml>, String> {
+    field(yaml, name)?.as_vec().ok_or_else(|| format!("{} must be an array in snapshots.", name))
+}
+
+pub fn usize_from(yaml: &Yaml) -> Result<usize, String> {
+    yaml.as_i64().and_then(|n| usize::try_from(n).ok()).ok_or_else(|| "Ids, indices and sizes must be numbers that aren't negative in snapshots.".to_string())
+}
+
+pub fn usizes_from(yaml: &Yaml) -> Result<Vec<usize>, String> {
+    yaml.as_vec().ok_or("Indices must be arrays in snapshots.")?.iter().map(usize_from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kodit::io_backend::MemoryIo;
+    use crate::kodit::{lexer, lexing_specification, line};
+
+    const PROGRAM: &str = r#"table t 3
+put t 0 5
+list l 1 2
+dictionary d
+put d "k" l
+set i 0
+label loop
+push l i
+sum i + 1
+set i @save
+random integer 1 100
+say @save
+say " "
+sum i < 5
+if @save loop next
+get d "k"
+size @save
+say @save
+get t 0
+say @save
+set @save 0
+"#;
+
+    fn lexed(source: &str) -> Vec<Line> {
+        let lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+        let specs = lexing_specification::load(&[]).unwrap();
+        lexer::lex(&line::decompose_lines(&lines).unwrap(), &specs).unwrap()
+    }
+
+    fn vm_with_output() -> (VM, MemoryIo) {
+        let io = MemoryIo::new();
+        let mut vm = VM::new();
+        vm.io = Box::new(io.clone());
+        vm.seed_random(7);
+        (vm, io)
+    }
+
+    #[test]
+    fn a_restored_program_continues_like_the_original() {
+        let file = "program.kdt".to_string();
+        let (mut original, original_io) = vm_with_output();
+        original.load(&file, lexed(PROGRAM)).unwrap();
+        assert!(matches!(original.run_for(20), Status::Running));
+
+        let snapshot = save(&original).unwrap();
+        let before_snapshot = original_io.take_output();
+
+        let (mut restored, restored_io) = vm_with_output();
+        restore(&mut restored, &file, lexed(PROGRAM), &snapshot).unwrap();
+
+        assert_eq!(original.run_to_end().unwrap(), 0);
+        assert_eq!(restored.run_to_end().unwrap(), 0);
+        assert!(!before_snapshot.is_empty());
+        assert_eq!(restored_io.take_output(), original_io.take_output());
+    }
+
+    #[test]
+    fn saving_a_restored_program_gives_the_same_snapshot() {
+        let file = "program.kdt".to_string();
+        let (mut original, _) = vm_with_output();
+        original.load(&file, lexed(PROGRAM)).unwrap();
+        original.run_for(25);
+
+        let snapshot = save(&original).unwrap();
+        let (mut restored, _) = vm_with_output();
+        restore(&mut restored, &file, lexed(PROGRAM), &snapshot).unwrap();
+
+        assert_eq!(save(&restored).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn snapshots_of_other_programs_are_refused() {
+        let file = "program.kdt".to_string();
+        let (mut original, _) = vm_with_output();
+        original.load(&file, lexed(PROGRAM)).unwrap();
+        original.run_for(5);
+        let snapshot = save(&original).unwrap();
+
+        let (mut other, _) = vm_with_output();
+        let changed = PROGRAM.replace("put t 0 5", "put t 0 6");
+        assert!(restore(&mut other, &file, lexed(&changed), &snapshot).is_err());
+        assert!(restore(&mut other, &file, lexed(PROGRAM), &snapshot.replace("version: 1", "version: 2")).is_err());
+    }
+}
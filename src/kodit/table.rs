@@ -1,17 +1,120 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
+
+use yaml_rust::Yaml;
 
 use super::{value::{Value, ValueType}, environment::Environment};
+use super::snapshot::{array_of, field, hash, integer, usize_of, usizes, usizes_from, value_from_yaml, value_to_yaml};
 
 pub struct Table {
     dimensions: Vec<usize>,
     data: Box<[Value]>,
     is_marked: bool,
-    is_handled: bool,
 }
 
+/// Integral numbers are keyed as integers so 3 and 3.0 are the same key.
+/// Other numbers are keyed by their bits since floats can't be hashed.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum DictionaryKey {
+    String(String),
+    Integer(BigInt),
+    Number(u64),
+}
+
+impl DictionaryKey {
+    pub fn from_value(value: &Value) -> Result<DictionaryKey, &'static str> {
+        match value.value_type {
+            ValueType::Number => Ok(match (&value.integer_value, BigInt::from_f64(value.number_value)) {
+                (Some(n), _) => DictionaryKey::Integer(n.clone()),
+                (None, Some(n)) if value.number_value.fract() == 0.0 => DictionaryKey::Integer(n),
+                (None, _) => DictionaryKey::Number(value.number_value.to_bits()),
+            }),
+            ValueType::String => Ok(DictionaryKey::String(value.string_value.clone().unwrap())),
+            _ => Err("Dictionary keys must be strings or numbers."),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            DictionaryKey::String(s) => Value::string_value(s),
+            DictionaryKey::Integer(n) => Value::integer_value(n.clone()),
+            DictionaryKey::Number(bits) => Value::number_value(f64::from_bits(*bits)),
+        }
+    }
+}
+
+pub struct Dictionary {
+    /// Keys in insertion order.
+    keys: Vec<DictionaryKey>,
+    entries: HashMap<DictionaryKey, Value>,
+    is_marked: bool,
+}
+
+/// A growable one-dimensional list. The ring buffer makes pushing and popping
+/// at both ends amortized constant time.
+pub struct List {
+    items: VecDeque<Value>,
+    is_marked: bool,
+}
+
+/// A view into part of a table. An index into the view is multiplied by the stride
+/// and moved by the offset to get the index into the table.
+#[derive(Clone, PartialEq)]
+pub struct TableSlice {
+    pub offset: Vec<usize>,
+    pub extent: Vec<usize>,
+    pub stride: Vec<usize>,
+}
+
+impl TableSlice {
+    pub fn whole(dimensions: &[usize]) -> TableSlice {
+        TableSlice {
+            offset: vec![0; dimensions.len()],
+            extent: dimensions.to_vec(),
+            stride: vec![1; dimensions.len()],
+        }
+    }
+
+    /// Slices this view further with indices relative to it. The end indices are exclusive.
+    pub fn slice(&self, start: &[usize], end: &[usize], stride: &[usize]) -> Result<TableSlice, &'static str> {
+        let n = self.extent.len();
+
+        if start.len() != n || end.len() != n || stride.len() != n {
+            return Err("Dimension numbers don't match.");
+        }
+
+        if (0..n).any(|i| start[i] > end[i] || end[i] > self.extent[i]) {
+            return Err("Slice bounds must be ordered and within the table.");
+        }
+
+        if stride.contains(&0) {
+            return Err("Slice stride must be positive.");
+        }
+
+        Ok(TableSlice {
+            offset: (0..n).map(|i| self.offset[i] + start[i] * self.stride[i]).collect(),
+            extent: (0..n).map(|i| (end[i] - start[i]).div_ceil(stride[i])).collect(),
+            stride: (0..n).map(|i| self.stride[i] * stride[i]).collect(),
+        })
+    }
+}
+
+/// Owns every table, dictionary and list. They share the same ids and are garbage collected together.
 pub struct TableManager {
     table_id: usize,
     tables: HashMap<usize, Table>,
+    dictionaries: HashMap<usize, Dictionary>,
+    lists: HashMap<usize, List>,
+    /// The number of cells in all tables and items in all lists and dictionaries, including
+    /// ones that are not collected yet.
+    cell_count: usize,
+    /// Tables, dictionaries and lists made since the manager was.
+    created: u64,
+    /// The ones made while the running line is evaluated. They can be held only by the
+    /// interpreter until the line assigns them, so they are kept alive until the next line.
+    in_flight: Vec<usize>,
 }
 
 // Constructor
@@ -20,28 +123,42 @@ impl TableManager {
         TableManager {
             table_id: 0,
             tables: HashMap::new(),
+            dictionaries: HashMap::new(),
+            lists: HashMap::new(),
+            cell_count: 0,
+            created: 0,
+            in_flight: vec!(),
         }
     }
 }
 
 // Read/Write
 impl TableManager {
-    fn calculate_data_index(table: &Table, table_value: &Value, mut dimensions: Vec<usize>) -> Result<usize, &'static str> {
+    fn calculate_data_index(table: &Table, table_value: &Value, mut dimensions: Vec<usize>) -> Result<usize, String> {
         if table.dimensions.len() != dimensions.len() {
-            return Err("Dimension numbers don't match.")
+            return Err("Dimension numbers don't match.".to_string())
         }
 
-        dimensions.iter_mut()
-            .zip(table_value.slice_offset.as_ref().ok_or("Value is not a table.")?)
-            .for_each(|(a, b)| *a += b);
+        if let Some(slice) = &table_value.slice {
+            let check_slice = dimensions.iter()
+                .zip(slice.extent.iter())
+                .all(|(a, b)| a < b);
+
+            if !check_slice {
+                return Err(format!("Index out of bounds for indices {:?} out of the slice {:?}.", dimensions, slice.extent));
+            }
+
+            dimensions.iter_mut()
+                .zip(slice.offset.iter().zip(slice.stride.iter()))
+                .for_each(|(a, (offset, stride))| *a = offset + *a * stride);
+        }
 
         let check_each_dimension = dimensions.iter()
             .zip(table.dimensions.iter())
             .all(|(a, b)| a >= &0 && a < b);
         
         if !check_each_dimension {
-            print!("Index out of bounds for indices {:?} out of {:?}.", dimensions, table.dimensions);
-            return Err("Index out of bounds. See above.");
+            return Err(format!("Index out of bounds for indices {:?} out of {:?}.", dimensions, table.dimensions));
         }
 
         let number = dimensions.iter()
@@ -51,21 +168,38 @@ impl TableManager {
         Ok(number)
     }
 
-    pub fn get_dimensions(&self, table_value: &Value) -> Result<&Vec<usize>, &'static str> {
+    /// The dimensions of the table, or of the slice if the value refers to one.
+    pub fn get_dimensions(&self, table_value: &Value) -> Result<Vec<usize>, &'static str> {
         let table = match self.tables.get(&table_value.table_index) {
             Some(t) => t,
             None => return Err("Table not found by index.")
         };
 
-        Ok(&table.dimensions)
+        match &table_value.slice {
+            Some(slice) => Ok(slice.extent.clone()),
+            None => Ok(table.dimensions.clone()),
+        }
     }
 
-    pub fn get(&self, table_value: &Value, dimensions: Vec<usize>) -> Result<&Value, &'static str> {
+    /// The view a value has into its table, covering the whole table if the value isn't a slice.
+    pub fn get_slice(&self, table_value: &Value) -> Result<TableSlice, &'static str> {
         let table = match self.tables.get(&table_value.table_index) {
             Some(t) => t,
             None => return Err("Table not found by index.")
         };
 
+        match &table_value.slice {
+            Some(slice) => Ok(slice.clone()),
+            None => Ok(TableSlice::whole(&table.dimensions)),
+        }
+    }
+
+    pub fn get(&self, table_value: &Value, dimensions: Vec<usize>) -> Result<&Value, String> {
+        let table = match self.tables.get(&table_value.table_index) {
+            Some(t) => t,
+            None => return Err("Table not found by index.".to_string())
+        };
+
         let index = match Self::calculate_data_index(table, table_value, dimensions) {
             Ok(idx) => idx,
             Err(e) => return Err(e),
@@ -74,16 +208,16 @@ impl TableManager {
         let candidate_value = table.data.get(index).unwrap();
 
         if candidate_value.is_uninitialized() {
-            Err("Field is not initialized.")
+            Err("Field is not initialized.".to_string())
         } else {
             Ok(candidate_value)
         }
     }
 
-    pub fn put(&mut self, table_value: &Value, dimensions: Vec<usize>, value: Value) -> Result<(), &'static str> {
+    pub fn put(&mut self, table_value: &Value, dimensions: Vec<usize>, value: Value) -> Result<(), String> {
         let table = match self.tables.get_mut(&table_value.table_index) {
             Some(t) => t,
-            None => return Err("Table not found by index.")
+            None => return Err("Table not found by index.".to_string())
         };
 
         let index = match Self::calculate_data_index(table, table_value, dimensions) {
@@ -104,7 +238,7 @@ impl TableManager {
         let max_capacity: usize = table.dimensions.iter().product();
 
         if data.len() > max_capacity {
-            panic!("Data to fill in is too much.");
+            return Err("The data doesn't fit in the table.");
         }
 
         table.data[0..data.len()].clone_from_slice(data);
@@ -112,38 +246,549 @@ impl TableManager {
     }
 }
 
-// Garbage Collection
+// Bulk Operations
 impl TableManager {
-    pub fn create_table(&mut self, dimensions: &Vec<usize>) -> Value {
+    /// Every index of a table with the given dimensions in row-major order.
+    pub fn row_major_indices(dimensions: &[usize]) -> Vec<Vec<usize>> {
         let length: usize = dimensions.iter().product();
+        let mut indices = Vec::with_capacity(length);
+        let mut current = vec![0usize; dimensions.len()];
 
-        let table = Table {
-            dimensions: dimensions.clone(),
-            data: vec![Value::uninitialized(); length].into_boxed_slice(),
-            is_marked: false,
-            is_handled: false,
+        for _ in 0..length {
+            indices.push(current.clone());
+
+            for d in (0..dimensions.len()).rev() {
+                current[d] += 1;
+                if current[d] < dimensions[d] {
+                    break;
+                }
+                current[d] = 0;
+            }
+        }
+
+        indices
+    }
+
+    /// The index of the cell at a position in row-major order.
+    pub fn unravel(dimensions: &[usize], mut position: usize) -> Vec<usize> {
+        let mut index = vec![0usize; dimensions.len()];
+
+        for d in (0..dimensions.len()).rev() {
+            index[d] = position % dimensions[d];
+            position /= dimensions[d];
+        }
+
+        index
+    }
+
+    /// The positions in the table data of every cell the value can see, in row-major order.
+    /// The indices are inside of the value, so they can only miss the table if it was reshaped
+    /// or resized after the slice was taken.
+    fn data_indices(&self, table_value: &Value) -> Result<Vec<usize>, &'static str> {
+        let table = match self.tables.get(&table_value.table_index) {
+            Some(t) => t,
+            None => return Err("Table not found by index.")
         };
 
+        Self::row_major_indices(&self.get_dimensions(table_value)?).into_iter()
+            .map(|index| Self::calculate_data_index(table, table_value, index).map_err(|_| "The slice doesn't fit in its table anymore."))
+            .collect()
+    }
+
+    /// The cells the value can see in row-major order, including uninitialized ones.
+    pub fn cell_values(&self, table_value: &Value) -> Result<Vec<Value>, &'static str> {
+        let table = match self.tables.get(&table_value.table_index) {
+            Some(t) => t,
+            None => return Err("Table not found by index.")
+        };
+
+        Ok(self.data_indices(table_value)?.into_iter().map(|i| table.data[i].clone()).collect())
+    }
+
+    pub fn fill(&mut self, table_value: &Value, value: &Value) -> Result<(), &'static str> {
+        let indices = self.data_indices(table_value)?;
+        let table = self.tables.get_mut(&table_value.table_index).unwrap();
+
+        for i in indices {
+            table.data[i] = value.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Makes a new table with the cells the value can see. Tables inside it are shared with the original.
+    pub fn copy(&mut self, table_value: &Value) -> Result<Value, &'static str> {
+        let dimensions = self.get_dimensions(table_value)?;
+        let data = self.cell_values(table_value)?;
+
+        let copy = self.create_table(&dimensions);
+        self.write_raw(&copy, &data)?;
+        Ok(copy)
+    }
+
+    /// Copies the table and every table, dictionary and list reachable from it. Values
+    /// referenced from several places stay shared between those places in the copy.
+    pub fn deep_copy(&mut self, table_value: &Value) -> Result<Value, &'static str> {
+        let copy = self.copy(table_value)?;
+
+        let mut copies: HashMap<usize, usize> = HashMap::new();
+        if table_value.slice.is_none() {
+            copies.insert(table_value.table_index, copy.table_index);
+        }
+
+        let mut to_visit = vec![copy.table_index];
+
+        while let Some(id) = to_visit.pop() {
+            let children: Vec<Value> = if let Some(table) = self.tables.get(&id) {
+                table.data.to_vec()
+            } else if let Some(dictionary) = self.dictionaries.get(&id) {
+                dictionary.keys.iter().map(|key| dictionary.entries[key].clone()).collect()
+            } else if let Some(list) = self.lists.get(&id) {
+                list.items.iter().cloned().collect()
+            } else {
+                continue;
+            };
+
+            let mut copied = Vec::with_capacity(children.len());
+            for value in children {
+                let original = match Self::reference(&value) {
+                    Some(original) => original,
+                    None => {
+                        copied.push(value);
+                        continue;
+                    },
+                };
+
+                let new_id = match copies.get(&original) {
+                    Some(new_id) => *new_id,
+                    None => {
+                        let new_id = self.copy_container(original)?;
+                        copies.insert(original, new_id);
+                        to_visit.push(new_id);
+                        new_id
+                    },
+                };
+
+                copied.push(Value { table_index: new_id, ..value });
+            }
+
+            if let Some(table) = self.tables.get_mut(&id) {
+                table.data = copied.into();
+            } else if let Some(dictionary) = self.dictionaries.get_mut(&id) {
+                for (key, value) in dictionary.keys.iter().zip(copied) {
+                    dictionary.entries.insert(key.clone(), value);
+                }
+            } else if let Some(list) = self.lists.get_mut(&id) {
+                list.items = copied.into();
+            }
+        }
+
+        Ok(copy)
+    }
+
+    /// Makes a shallow copy of the whole table, dictionary or list with the id and gives the id of the copy.
+    fn copy_container(&mut self, id: usize) -> Result<usize, &'static str> {
+        if let Some(table) = self.tables.get(&id) {
+            let (dimensions, data) = (table.dimensions.clone(), table.data.to_vec());
+            let copy = self.create_table(&dimensions);
+            self.write_raw(&copy, &data)?;
+            Ok(copy.table_index)
+        } else if let Some(dictionary) = self.dictionaries.get(&id) {
+            let (keys, entries) = (dictionary.keys.clone(), dictionary.entries.clone());
+            let copy = self.create_dictionary();
+            self.cell_count += keys.len();
+            let copied = self.dictionaries.get_mut(&copy.table_index).unwrap();
+            copied.keys = keys;
+            copied.entries = entries;
+            Ok(copy.table_index)
+        } else if let Some(list) = self.lists.get(&id) {
+            let items = list.items.iter().cloned().collect();
+            Ok(self.create_list(items).table_index)
+        } else {
+            Err("Table not found by index.")
+        }
+    }
+
+    /// Changes the dimensions of the whole table keeping the cells in row-major order.
+    pub fn reshape(&mut self, table_value: &Value, dimensions: &[usize]) -> Result<(), &'static str> {
+        let table = self.whole_table_mut(table_value)?;
+
+        if dimensions.iter().product::<usize>() != table.data.len() {
+            return Err("Reshaping must keep the number of cells the same.");
+        }
+
+        table.dimensions = dimensions.to_vec();
+        Ok(())
+    }
+
+    /// Grows or shrinks one dimension of the whole table. Cells keep their indices,
+    /// cells that fall outside are dropped and new cells are uninitialized.
+    pub fn resize(&mut self, table_value: &Value, dimension: usize, size: usize) -> Result<(), &'static str> {
+        let table = self.whole_table_mut(table_value)?;
+
+        if dimension >= table.dimensions.len() {
+            return Err("The table doesn't have such a dimension.");
+        }
+
+        let mut new_dimensions = table.dimensions.clone();
+        new_dimensions[dimension] = size;
+
+        let new_length: usize = new_dimensions.iter().product();
+        let mut new_data = vec![Value::uninitialized(); new_length];
+
+        for (old_position, index) in Self::row_major_indices(&table.dimensions).into_iter().enumerate() {
+            if index[dimension] < size {
+                let new_position = index.iter()
+                    .zip(new_dimensions.iter())
+                    .fold(0usize, |acc, (idx, dim)| dim * acc + idx);
+                new_data[new_position] = table.data[old_position].clone();
+            }
+        }
+
+        let old_length = table.data.len();
+        table.dimensions = new_dimensions;
+        table.data = new_data.into_boxed_slice();
+        self.cell_count = self.cell_count + new_length - old_length;
+        Ok(())
+    }
+
+    /// Reorders the cells the value can see with a Fisher-Yates shuffle, where
+    /// `below(n)` picks a position less than n.
+    pub fn shuffle(&mut self, table_value: &Value, mut below: impl FnMut(usize) -> usize) -> Result<(), &'static str> {
+        let indices = self.data_indices(table_value)?;
+        let table = self.tables.get_mut(&table_value.table_index).unwrap();
+
+        for i in (1..indices.len()).rev() {
+            table.data.swap(indices[i], indices[below(i + 1)]);
+        }
+
+        Ok(())
+    }
+
+    fn whole_table_mut(&mut self, table_value: &Value) -> Result<&mut Table, &'static str> {
+        if table_value.slice.is_some() {
+            return Err("Only whole tables can change their dimensions, not slices.");
+        }
+
+        match self.tables.get_mut(&table_value.table_index) {
+            Some(t) => Ok(t),
+            None => Err("Table not found by index."),
+        }
+    }
+}
+
+// Dictionaries
+impl TableManager {
+    fn dictionary(&self, dictionary_value: &Value) -> Result<&Dictionary, &'static str> {
+        self.dictionaries.get(&dictionary_value.table_index).ok_or("Dictionary not found by index.")
+    }
+
+    fn dictionary_mut(&mut self, dictionary_value: &Value) -> Result<&mut Dictionary, &'static str> {
+        self.dictionaries.get_mut(&dictionary_value.table_index).ok_or("Dictionary not found by index.")
+    }
+
+    pub fn dictionary_get(&self, dictionary_value: &Value, key: &Value) -> Result<&Value, &'static str> {
+        self.dictionary(dictionary_value)?.entries.get(&DictionaryKey::from_value(key)?).ok_or("Key is not in the dictionary.")
+    }
+
+    pub fn dictionary_put(&mut self, dictionary_value: &Value, key: &Value, value: Value) -> Result<(), &'static str> {
+        let key = DictionaryKey::from_value(key)?;
+        let dictionary = self.dictionary_mut(dictionary_value)?;
+
+        if dictionary.entries.insert(key.clone(), value).is_none() {
+            dictionary.keys.push(key);
+            self.cell_count += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the key was in the dictionary.
+    /// Returns the position the key had in the key order, or None if it wasn't there.
+    pub fn dictionary_delete(&mut self, dictionary_value: &Value, key: &Value) -> Result<Option<usize>, &'static str> {
+        let key = DictionaryKey::from_value(key)?;
+        let dictionary = self.dictionary_mut(dictionary_value)?;
+
+        if dictionary.entries.remove(&key).is_none() {
+            return Ok(None);
+        }
+
+        let position = dictionary.keys.iter().position(|k| k == &key).unwrap();
+        dictionary.keys.remove(position);
+        self.cell_count -= 1;
+        Ok(Some(position))
+    }
+
+    pub fn dictionary_contains(&self, dictionary_value: &Value, key: &Value) -> Result<bool, &'static str> {
+        Ok(self.dictionary(dictionary_value)?.entries.contains_key(&DictionaryKey::from_value(key)?))
+    }
+
+    pub fn dictionary_len(&self, dictionary_value: &Value) -> Result<usize, &'static str> {
+        Ok(self.dictionary(dictionary_value)?.keys.len())
+    }
+
+    /// The key at a position in insertion order.
+    pub fn dictionary_key_at(&self, dictionary_value: &Value, position: usize) -> Result<Option<Value>, &'static str> {
+        Ok(self.dictionary(dictionary_value)?.keys.get(position).map(|k| k.to_value()))
+    }
+
+    /// The keys in the order they were first put into the dictionary.
+    pub fn dictionary_keys(&self, dictionary_value: &Value) -> Result<Vec<Value>, &'static str> {
+        Ok(self.dictionary(dictionary_value)?.keys.iter().map(|k| k.to_value()).collect())
+    }
+}
+
+// Lists
+impl TableManager {
+    fn list(&self, list_value: &Value) -> Result<&List, &'static str> {
+        self.lists.get(&list_value.table_index).ok_or("List not found by index.")
+    }
+
+    fn list_mut(&mut self, list_value: &Value) -> Result<&mut List, &'static str> {
+        self.lists.get_mut(&list_value.table_index).ok_or("List not found by index.")
+    }
+
+    pub fn list_len(&self, list_value: &Value) -> Result<usize, &'static str> {
+        Ok(self.list(list_value)?.items.len())
+    }
+
+    pub fn list_get(&self, list_value: &Value, index: usize) -> Result<&Value, &'static str> {
+        self.list(list_value)?.items.get(index).ok_or("Index out of bounds of the list.")
+    }
+
+    pub fn list_put(&mut self, list_value: &Value, index: usize, value: Value) -> Result<(), &'static str> {
+        let item = self.list_mut(list_value)?.items.get_mut(index).ok_or("Index out of bounds of the list.")?;
+        *item = value;
+        Ok(())
+    }
+
+    /// Inserting at the length of the list appends to it.
+    pub fn list_insert(&mut self, list_value: &Value, index: usize, value: Value) -> Result<(), &'static str> {
+        let list = self.list_mut(list_value)?;
+
+        if index > list.items.len() {
+            return Err("Index out of bounds of the list.");
+        }
+
+        list.items.insert(index, value);
+        self.cell_count += 1;
+        Ok(())
+    }
+
+    pub fn list_remove(&mut self, list_value: &Value, index: usize) -> Result<Value, &'static str> {
+        let item = self.list_mut(list_value)?.items.remove(index).ok_or("Index out of bounds of the list.")?;
+        self.cell_count -= 1;
+        Ok(item)
+    }
+
+    pub fn list_shuffle(&mut self, list_value: &Value, mut below: impl FnMut(usize) -> usize) -> Result<(), &'static str> {
+        let items = &mut self.list_mut(list_value)?.items;
+
+        for i in (1..items.len()).rev() {
+            items.swap(i, below(i + 1));
+        }
+
+        Ok(())
+    }
+
+    /// Sorts by "number" or "string" ordering, inferred from the first item if not given.
+    pub fn list_sort(&mut self, list_value: &Value, ordering: Option<&str>) -> Result<(), &'static str> {
+        let list = self.list_mut(list_value)?;
+
+        let by_number = match ordering {
+            Some("number") => true,
+            Some("string") => false,
+            Some(_) => return Err("The ordering must be number or string."),
+            None => !matches!(list.items.front().map(|v| &v.value_type), Some(ValueType::String)),
+        };
+
+        let all_match = list.items.iter().all(|v| if by_number {
+            matches!(v.value_type, ValueType::Number)
+        } else {
+            matches!(v.value_type, ValueType::String)
+        });
+
+        if !all_match {
+            return Err(if by_number { "Every item must be a number to sort by number." } else { "Every item must be a string to sort by string." });
+        }
+
+        // Numbers are ordered by their float value first, which is a total order, and integers
+        // too large to tell apart as floats by their exact value after that.
+        list.items.make_contiguous().sort_by(|a, b| if by_number {
+            a.number_value.total_cmp(&b.number_value).then_with(|| a.integer_value.cmp(&b.integer_value))
+        } else {
+            a.string_value.cmp(&b.string_value)
+        });
+
+        Ok(())
+    }
+}
+
+// Snapshots
+impl TableManager {
+    /// Every table, dictionary and list with its id, so values that share one still share it when restored.
+    pub fn to_yaml(&self) -> Yaml {
+        let mut table_ids: Vec<&usize> = self.tables.keys().collect();
+        table_ids.sort();
+        let mut dictionary_ids: Vec<&usize> = self.dictionaries.keys().collect();
+        dictionary_ids.sort();
+        let mut list_ids: Vec<&usize> = self.lists.keys().collect();
+        list_ids.sort();
+
+        hash(vec!(
+            ("next_id", integer(self.table_id)),
+            ("tables", Yaml::Array(table_ids.into_iter().map(|id| hash(vec!(
+                ("id", integer(*id)),
+                ("dimensions", usizes(&self.tables[id].dimensions)),
+                ("cells", Yaml::Array(self.tables[id].data.iter().map(value_to_yaml).collect())),
+            ))).collect())),
+            ("dictionaries", Yaml::Array(dictionary_ids.into_iter().map(|id| {
+                let dictionary = &self.dictionaries[id];
+                hash(vec!(
+                    ("id", integer(*id)),
+                    ("entries", Yaml::Array(dictionary.keys.iter().map(|key| Yaml::Array(vec!(
+                        value_to_yaml(&key.to_value()),
+                        value_to_yaml(&dictionary.entries[key]),
+                    ))).collect())),
+                ))
+            }).collect())),
+            ("lists", Yaml::Array(list_ids.into_iter().map(|id| hash(vec!(
+                ("id", integer(*id)),
+                ("items", Yaml::Array(self.lists[id].items.iter().map(value_to_yaml).collect())),
+            ))).collect())),
+        ))
+    }
+
+    pub fn from_yaml(yaml: &Yaml) -> Result<TableManager, String> {
+        let mut manager = TableManager::new();
+        manager.table_id = usize_of(yaml, "next_id")?;
+
+        for table in array_of(yaml, "tables")? {
+            let dimensions = usizes_from(field(table, "dimensions")?)?;
+            let data = array_of(table, "cells")?.iter().map(value_from_yaml).collect::<Result<Vec<Value>, String>>()?;
+            if data.len() != dimensions.iter().product::<usize>() {
+                return Err("The cells of a table don't match its dimensions in the snapshot.".to_string());
+            }
+            manager.cell_count += data.len();
+            manager.tables.insert(usize_of(table, "id")?, Table { dimensions, data: data.into_boxed_slice(), is_marked: false });
+        }
+
+        for dictionary in array_of(yaml, "dictionaries")? {
+            let mut restored = Dictionary { keys: vec!(), entries: HashMap::new(), is_marked: false };
+            for entry in array_of(dictionary, "entries")? {
+                let (key, value) = match entry.as_vec().map(|pair| pair.as_slice()) {
+                    Some([key, value]) => (DictionaryKey::from_value(&value_from_yaml(key)?)?, value_from_yaml(value)?),
+                    _ => return Err("Dictionary entries must be a key and a value in snapshots.".to_string()),
+                };
+                restored.keys.push(key.clone());
+                restored.entries.insert(key, value);
+            }
+            manager.cell_count += restored.keys.len();
+            manager.dictionaries.insert(usize_of(dictionary, "id")?, restored);
+        }
+
+        for list in array_of(yaml, "lists")? {
+            let items = array_of(list, "items")?.iter().map(value_from_yaml).collect::<Result<VecDeque<Value>, String>>()?;
+            manager.cell_count += items.len();
+            manager.lists.insert(usize_of(list, "id")?, List { items, is_marked: false });
+        }
+
+        Ok(manager)
+    }
+}
+
+// Garbage Collection
+impl TableManager {
+    fn next_id(&mut self) -> usize {
         if self.table_id == usize::MAX {
             //panic!("Runtime Error: Maximum number of tables have been created!");
             self.table_id = 0;
         }
 
-        while self.tables.contains_key(&self.table_id) {
+        while self.tables.contains_key(&self.table_id) || self.dictionaries.contains_key(&self.table_id) || self.lists.contains_key(&self.table_id) {
             self.table_id += 1;
         }
 
         let id = self.table_id;
         self.table_id += 1;
+        self.created += 1;
+        self.in_flight.push(id);
+        id
+    }
+
+    pub fn create_table(&mut self, dimensions: &Vec<usize>) -> Value {
+        let length: usize = dimensions.iter().product();
+
+        let table = Table {
+            dimensions: dimensions.clone(),
+            data: vec![Value::uninitialized(); length].into_boxed_slice(),
+            is_marked: false,
+        };
+
+        let id = self.next_id();
 
         self.tables.insert(id, table);
+        self.cell_count += length;
 
         Value {
             table_index: id,
             value_type: ValueType::Table,
             string_value: None,
             number_value: 0f64,
-            slice_offset: Some(vec![0usize; dimensions.len()]),
+            integer_value: None,
+            slice: None,
+        }
+    }
+
+    pub fn create_dictionary(&mut self) -> Value {
+        let dictionary = Dictionary {
+            keys: vec!(),
+            entries: HashMap::new(),
+            is_marked: false,
+        };
+
+        let id = self.next_id();
+
+        self.dictionaries.insert(id, dictionary);
+
+        Value {
+            table_index: id,
+            value_type: ValueType::Dictionary,
+            string_value: None,
+            number_value: 0f64,
+            integer_value: None,
+            slice: None,
+        }
+    }
+
+    pub fn create_list(&mut self, items: Vec<Value>) -> Value {
+        self.cell_count += items.len();
+
+        let list = List {
+            items: items.into(),
+            is_marked: false,
+        };
+
+        let id = self.next_id();
+
+        self.lists.insert(id, list);
+
+        Value {
+            table_index: id,
+            value_type: ValueType::List,
+            string_value: None,
+            number_value: 0f64,
+            integer_value: None,
+            slice: None,
+        }
+    }
+
+    /// The id of the table, dictionary or list the value refers to.
+    fn reference(value: &Value) -> Option<usize> {
+        match value.value_type {
+            ValueType::Table if !value.is_uninitialized() => Some(value.table_index),
+            ValueType::Dictionary | ValueType::List => Some(value.table_index),
+            _ => None,
         }
     }
 
@@ -152,38 +797,44 @@ impl TableManager {
         // it doesn't run like a queue but it doesn't matter.
         let mut queue: HashSet<usize> = HashSet::new();
         
-        // clear the marking on all tables
-        for (_, table) in &mut self.tables {
+        // clear the marking on all tables, dictionaries and lists
+        for table in self.tables.values_mut() {
             table.is_marked = false;
         }
+        for dictionary in self.dictionaries.values_mut() {
+            dictionary.is_marked = false;
+        }
+        for list in self.lists.values_mut() {
+            list.is_marked = false;
+        }
 
         // add root pointers to the queue
         for env in environments {
-            for (_, value) in &env.variables {
-                if !value.is_uninitialized() && matches!(value.value_type, ValueType::Table) {
-                    queue.insert(value.table_index);
-                }
-            }
+            queue.extend(env.variables.values().filter_map(Self::reference));
         }
+        queue.extend(self.in_flight.iter().copied());
 
-        while !queue.is_empty() {
-            let next_index = queue.iter().next().unwrap().to_owned();
+        while let Some(next_index) = queue.iter().next().copied() {
             queue.remove(&next_index);
 
-            let next_table = self.tables.get_mut(&next_index).unwrap();
-
-            next_table.is_marked = true;
-
-            let next_table = self.tables.get(&next_index).unwrap();
-
-            for value in next_table.data.iter() {
-                if !value.is_uninitialized() && matches!(value.value_type, ValueType::Table) {
-                    let next_table = self.tables.get(&value.table_index).unwrap();
-                    if !next_table.is_handled {
-                        queue.insert(value.table_index);
-                    }
-                }
-            }
+            // Marked ones are skipped so reference cycles terminate.
+            let children: Vec<usize> = if let Some(table) = self.tables.get_mut(&next_index) {
+                if table.is_marked { continue; }
+                table.is_marked = true;
+                table.data.iter().filter_map(Self::reference).collect()
+            } else if let Some(dictionary) = self.dictionaries.get_mut(&next_index) {
+                if dictionary.is_marked { continue; }
+                dictionary.is_marked = true;
+                dictionary.entries.values().filter_map(Self::reference).collect()
+            } else if let Some(list) = self.lists.get_mut(&next_index) {
+                if list.is_marked { continue; }
+                list.is_marked = true;
+                list.items.iter().filter_map(Self::reference).collect()
+            } else {
+                continue;
+            };
+
+            queue.extend(children);
         }
     }
 
@@ -191,5 +842,155 @@ impl TableManager {
         self.tables.retain(|_, v| {
             v.is_marked
         });
+        self.dictionaries.retain(|_, v| {
+            v.is_marked
+        });
+        self.lists.retain(|_, v| {
+            v.is_marked
+        });
+        self.cell_count = self.tables.values().map(|table| table.data.len()).sum::<usize>()
+            + self.dictionaries.values().map(|dictionary| dictionary.keys.len()).sum::<usize>()
+            + self.lists.values().map(|list| list.items.len()).sum::<usize>();
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.cell_count
     }
-}
\ No newline at end of file
+
+    /// The cells and items of the table, dictionary or list and of everything reachable from it,
+    /// which is how many a deep copy of it makes at most.
+    pub fn reachable_cells(&self, value: &Value) -> usize {
+        let mut cells = match (&value.value_type, &value.slice) {
+            (ValueType::Table, Some(slice)) => slice.extent.iter().product(),
+            _ => 0,
+        };
+
+        let mut visited: HashSet<usize> = HashSet::new();
+        let mut to_visit: Vec<usize> = Self::reference(value).into_iter().collect();
+
+        while let Some(id) = to_visit.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+
+            let children: Vec<&Value> = if let Some(table) = self.tables.get(&id) {
+                table.data.iter().collect()
+            } else if let Some(dictionary) = self.dictionaries.get(&id) {
+                dictionary.entries.values().collect()
+            } else if let Some(list) = self.lists.get(&id) {
+                list.items.iter().collect()
+            } else {
+                continue;
+            };
+
+            // A slice only copies the cells it can see, which are counted above.
+            if id != value.table_index || value.slice.is_none() {
+                cells += children.len();
+            }
+            to_visit.extend(children.into_iter().filter_map(Self::reference));
+        }
+
+        cells
+    }
+
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    /// Lets garbage collection free what the finished line made but didn't keep.
+    pub fn end_line(&mut self) {
+        self.in_flight.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use super::*;
+
+    fn number(n: i64) -> Value {
+        Value::integer_value(BigInt::from(n))
+    }
+
+    /// A 4 by 5 table whose cells hold ten times the row plus the column.
+    fn numbered_table(manager: &mut TableManager) -> Value {
+        let table = manager.create_table(&vec![4, 5]);
+        let data = (0..4).flat_map(|row| (0..5).map(move |column| number(row * 10 + column))).collect();
+        manager.write_raw(&table, &data).unwrap();
+        table
+    }
+
+    fn sliced(table: &Value, slice: TableSlice) -> Value {
+        Value { slice: Some(slice), ..table.clone() }
+    }
+
+    fn numbers(values: Vec<Value>) -> Vec<i64> {
+        values.iter().map(|value| value.number_value as i64).collect()
+    }
+
+    #[test]
+    fn slices_of_slices_are_relative_to_the_view() {
+        let outer = TableSlice::whole(&[4, 5]).slice(&[1, 1], &[4, 5], &[1, 2]).unwrap();
+        assert_eq!((outer.offset.clone(), outer.extent.clone(), outer.stride.clone()), (vec![1, 1], vec![3, 2], vec![1, 2]));
+
+        let inner = outer.slice(&[1, 1], &[3, 2], &[2, 1]).unwrap();
+        assert_eq!((inner.offset, inner.extent, inner.stride), (vec![2, 3], vec![1, 1], vec![2, 2]));
+    }
+
+    #[test]
+    fn bad_slice_bounds_are_errors() {
+        let whole = TableSlice::whole(&[4, 5]);
+
+        assert!(whole.slice(&[2, 0], &[1, 5], &[1, 1]).is_err());
+        assert!(whole.slice(&[0, 0], &[4, 6], &[1, 1]).is_err());
+        assert!(whole.slice(&[0, 0], &[4, 5], &[1, 0]).is_err());
+        assert!(whole.slice(&[0], &[4], &[1]).is_err());
+    }
+
+    #[test]
+    fn slices_read_and_write_the_cells_of_the_table() {
+        let mut manager = TableManager::new();
+        let table = numbered_table(&mut manager);
+        let view = sliced(&table, TableSlice::whole(&[4, 5]).slice(&[1, 0], &[3, 5], &[1, 2]).unwrap());
+
+        assert_eq!(manager.get_dimensions(&view).unwrap(), vec![2, 3]);
+        assert_eq!(numbers(manager.cell_values(&view).unwrap()), vec![10, 12, 14, 20, 22, 24]);
+
+        manager.put(&view, vec![1, 2], number(-1)).unwrap();
+        assert_eq!(manager.get(&table, vec![2, 4]).unwrap().number_value, -1.0);
+
+        assert_eq!(manager.get(&view, vec![2, 0]).err(), Some("Index out of bounds for indices [2, 0] out of the slice [2, 3].".to_string()));
+        assert_eq!(manager.get(&table, vec![0, 5]).err(), Some("Index out of bounds for indices [0, 5] out of [4, 5].".to_string()));
+    }
+
+    #[test]
+    fn copies_of_slices_only_hold_the_visible_cells() {
+        let mut manager = TableManager::new();
+        let table = numbered_table(&mut manager);
+        let view = sliced(&table, TableSlice::whole(&[4, 5]).slice(&[0, 3], &[4, 5], &[2, 1]).unwrap());
+
+        let copy = manager.copy(&view).unwrap();
+        assert!(copy.slice.is_none());
+        assert_eq!(manager.get_dimensions(&copy).unwrap(), vec![2, 2]);
+        assert_eq!(numbers(manager.cell_values(&copy).unwrap()), vec![3, 4, 23, 24]);
+    }
+
+    #[test]
+    fn integers_and_floats_sort_in_one_order() {
+        let mut manager = TableManager::new();
+        let large = BigInt::from(1u64 << 53);
+        let list = manager.create_list(vec!(
+            Value::integer_value(&large + 1), number(3), Value::number_value((1u64 << 53) as f64), Value::integer_value(large.clone()), Value::number_value(2.5),
+        ));
+
+        manager.list_sort(&list, None).unwrap();
+
+        let sorted: Vec<(f64, Option<BigInt>)> = (0..5).map(|i| manager.list_get(&list, i).unwrap())
+            .map(|value| (value.number_value, value.integer_value.clone()))
+            .collect();
+        assert_eq!(sorted, vec!(
+            (2.5, None), (3.0, Some(BigInt::from(3))), ((1u64 << 53) as f64, None), ((1u64 << 53) as f64, Some(large.clone())), ((1u64 << 53) as f64, Some(large + 1)),
+        ));
+    }
+}
@@ -0,0 +1,345 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::file_system::FileSystemPolicy;
+use super::io_backend::MemoryIo;
+use super::lexer;
+use super::lexing_specification;
+use super::line::{Command, LineItem};
+use super::vm::VM;
+
+/// Programs that don't say otherwise in a .max-lines file are stopped after this many lines
+/// so a test that loops forever fails instead of hanging.
+const DEFAULT_MAX_LINES: u64 = 10_000_000;
+
+/// Lines of unchanged output shown around the lines that differ.
+const DIFF_CONTEXT: usize = 2;
+
+/// What a test program did, or is expected to do according to the files next to it.
+#[derive(PartialEq)]
+struct Outcome {
+    output: String,
+    exit_code: i32,
+    error: Option<String>,
+}
+
+/// Runs every .kdt file under the directory, or the file if it is one, and compares what it does
+/// with the files next to it: .out for the output, .exit for the exit code if it isn't 0 and .err
+/// for the error if there is one. A test program reads its input from .in, its arguments from
+/// .args, its lexing specifications from .lexer, one on every line, and its line limit from
+/// .max-lines. Programs with a .skip file, which says why, aren't run. Blessing writes what the
+/// programs did as the expected results instead.
+///
+/// Programs with functions named test_* are unit tests instead. Each of those functions is run
+/// on its own and passes if it returns without an error or a failed assertion. The lines of the
+/// program outside of its functions don't run before a test function, so a test has to set up
+/// its variables and tables itself, for example by calling a setup function.
+/// Gives whether every test passed.
+pub fn run_tests(directory: &Path, bless: bool) -> bool {
+    let mut programs = vec!();
+    if let Err(e) = find_programs(directory, &mut programs) {
+        println!("Cannot list {}: {}", directory.display(), e);
+        return false;
+    }
+    programs.sort();
+
+    let (mut passes, mut failures, mut skips) = (0, 0, 0);
+
+    for program in &programs {
+        if let Ok(reason) = fs::read_to_string(program.with_extension("skip")) {
+            println!("SKIP  {}: {}", program.display(), reason.trim());
+            skips += 1;
+            continue;
+        }
+
+        let actual = match load_program(program) {
+            Ok((mut vm, io)) => {
+                let test_functions = test_functions(&vm);
+                if !test_functions.is_empty() {
+                    for name in test_functions {
+                        match run_test_function(&mut vm, program, &name) {
+                            Ok(()) => {
+                                println!("PASS  {} {}", program.display(), name);
+                                passes += 1;
+                            },
+                            Err(e) => {
+                                println!("FAIL  {} {}", program.display(), name);
+                                println!("    {}", e);
+                                failures += 1;
+                            },
+                        }
+                    }
+                    continue;
+                }
+
+                match vm.run_to_end() {
+                    Ok(exit_code) => Outcome { output: io.take_output(), exit_code, error: None },
+                    Err(e) => Outcome { output: io.take_output(), exit_code: e.exit_code(), error: Some(e.to_string()) },
+                }
+            },
+            Err(outcome) => outcome,
+        };
+
+        if bless {
+            match write_expected(program, &actual) {
+                Ok(()) => {
+                    println!("BLESS {}", program.display());
+                    passes += 1;
+                },
+                Err(e) => {
+                    println!("FAIL  {}: {}", program.display(), e);
+                    failures += 1;
+                },
+            }
+            continue;
+        }
+
+        let expected = match read_expected(program) {
+            Ok(expected) => expected,
+            Err(e) => {
+                println!("FAIL  {}: {}", program.display(), e);
+                failures += 1;
+                continue;
+            },
+        };
+
+        if actual == expected {
+            println!("PASS  {}", program.display());
+            passes += 1;
+            continue;
+        }
+
+        println!("FAIL  {}", program.display());
+        failures += 1;
+
+        if actual.exit_code != expected.exit_code {
+            println!("    exit code {}, expected {}", actual.exit_code, expected.exit_code);
+        }
+
+        if actual.error != expected.error {
+            println!("    error: {}", actual.error.as_deref().unwrap_or("none"));
+            println!("    expected error: {}", expected.error.as_deref().unwrap_or("none"));
+        }
+
+        if actual.output != expected.output {
+            let lines = diff(&expected.output, &actual.output);
+            if lines.iter().any(|line| line.starts_with(['-', '+'])) {
+                println!("    output differs, - expected + actual:");
+                for line in lines {
+                    println!("    {}", line);
+                }
+            } else {
+                println!("    output differs in line endings");
+            }
+        }
+    }
+
+    println!();
+    let skipped = if skips > 0 { format!(", {} skipped", skips) } else { String::new() };
+    if bless {
+        println!("{} blessed or passed, {} failed{}", passes, failures, skipped);
+    } else {
+        println!("{} passed, {} failed{}", passes, failures, skipped);
+    }
+
+    failures == 0
+}
+
+fn find_programs(directory: &Path, programs: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if directory.is_file() {
+        programs.push(directory.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_programs(&path, programs)?;
+        } else if path.extension().is_some_and(|extension| extension == "kdt") {
+            programs.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// The lines of a file next to the program, or none if there is no such file.
+fn sidecar_lines(program: &Path, extension: &str) -> Vec<String> {
+    fs::read_to_string(program.with_extension(extension))
+        .map(|text| text.lines().map(|line| line.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Loads the program with in-memory input and output, and with the same random numbers every
+/// time. Programs that cannot be loaded give the outcome of trying to run them instead.
+fn load_program(program: &Path) -> Result<(VM, MemoryIo), Outcome> {
+    let file_name = program.display().to_string();
+
+    let code = lexing_specification::load(&sidecar_lines(program, "lexer"))
+        .and_then(|specs| lexer::lex_file(&file_name, &specs));
+
+    let code = match code {
+        Ok(code) => code,
+        Err(e) => return Err(Outcome { output: String::new(), exit_code: 1, error: Some(e) }),
+    };
+
+    let io = input(program);
+
+    let mut vm = VM::new();
+    vm.io = Box::new(io.clone());
+    vm.seed_random(0);
+    vm.set_arguments(&sidecar_lines(program, "args"));
+    let directory = match program.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    vm.file_system = FileSystemPolicy::restricted_to(vec!(directory.clone()));
+    vm.file_system.base_directory = Some(directory);
+    vm.limits.max_lines = Some(sidecar_lines(program, "max-lines").first()
+        .and_then(|max| max.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_LINES));
+
+    match vm.load(&file_name, code) {
+        Ok(()) => Ok((vm, io)),
+        Err(e) => Err(Outcome { output: String::new(), exit_code: e.exit_code(), error: Some(e.to_string()) }),
+    }
+}
+
+fn input(program: &Path) -> MemoryIo {
+    let io = MemoryIo::new();
+    for line in sidecar_lines(program, "in") {
+        io.push_input(&line);
+    }
+    io
+}
+
+/// The functions of the program whose names start with test_, in the order they are written.
+fn test_functions(vm: &VM) -> Vec<String> {
+    let mut functions: Vec<(usize, String)> = vm.labels.iter()
+        .filter(|(name, label)| name.starts_with("test_")
+            && matches!(vm.code[label.line_number].items[0], LineItem::Command(Command::FUNCTION)))
+        .map(|(name, label)| (label.line_number, name.clone()))
+        .collect();
+    functions.sort();
+
+    functions.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Every test function gets the input of the program again and the same random numbers.
+fn run_test_function(vm: &mut VM, program: &Path, name: &str) -> Result<(), String> {
+    vm.io = Box::new(input(program));
+    vm.seed_random(0);
+    vm.start_function(name)?;
+    vm.run_to_end().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn read_expected(program: &Path) -> Result<Outcome, String> {
+    let output = fs::read_to_string(program.with_extension("out"))
+        .map_err(|_| "There is no .out file, run the tests with --bless to make one.".to_string())?;
+
+    let exit_code = match fs::read_to_string(program.with_extension("exit")) {
+        Ok(text) => text.trim().parse().map_err(|_| format!("The .exit file must contain a number, not {}.", text.trim()))?,
+        Err(_) => 0,
+    };
+
+    let error = fs::read_to_string(program.with_extension("err")).ok()
+        .map(|text| text.trim_end().to_string());
+
+    Ok(Outcome { output, exit_code, error })
+}
+
+/// Files that would hold the default are removed so they don't go stale.
+fn write_expected(program: &Path, outcome: &Outcome) -> Result<(), String> {
+    let write = |extension: &str, content: Option<String>| {
+        let path = program.with_extension(extension);
+        let result = match content {
+            Some(content) => fs::write(&path, content),
+            None if path.exists() => fs::remove_file(&path),
+            None => Ok(()),
+        };
+        result.map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    };
+
+    write("out", Some(outcome.output.clone()))?;
+    write("exit", (outcome.exit_code != 0).then(|| format!("{}\n", outcome.exit_code)))?;
+    write("err", outcome.error.as_ref().map(|error| format!("{}\n", error)))
+}
+
+/// The lines of both texts with the ones only in the expected text marked with - and the ones
+/// only in the actual text marked with +. Long runs of equal lines are left out.
+fn diff(expected: &str, actual: &str) -> Vec<String> {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // The length of the longest common subsequence of the ends of the texts from every position.
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<(char, &str)> = vec!();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push((' ', expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', expected[i]));
+            i += 1;
+        } else {
+            lines.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = lines.iter().enumerate()
+        .filter(|(_, (mark, _))| *mark != ' ')
+        .map(|(index, _)| index)
+        .collect();
+
+    let mut shown = vec!();
+    let mut skipped = false;
+    for (index, (mark, line)) in lines.iter().enumerate() {
+        if changed.iter().any(|change| change.abs_diff(index) <= DIFF_CONTEXT) {
+            shown.push(format!("{} {}", mark, line));
+            skipped = false;
+        } else if !skipped {
+            shown.push("  ...".to_string());
+            skipped = true;
+        }
+    }
+
+    shown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+
+    #[test]
+    fn equal_texts_are_left_out() {
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n"), vec!["  ..."]);
+    }
+
+    #[test]
+    fn changed_lines_are_shown_with_their_context() {
+        let expected = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let actual = "1\n2\n3\n4\nfive\n6\n7\n8\n";
+
+        assert_eq!(diff(expected, actual), vec!["  ...", "  3", "  4", "- 5", "+ five", "  6", "  7", "  ..."]);
+    }
+
+    #[test]
+    fn added_and_removed_lines_are_marked() {
+        assert_eq!(diff("a\nb\n", "a\nb\nc\n"), vec!["  a", "  b", "+ c"]);
+        assert_eq!(diff("a\nb\nc\n", "a\nc\n"), vec!["  a", "- b", "  c"]);
+        assert_eq!(diff("", "a\n"), vec!["+ a"]);
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::Write;
+use std::time::Duration;
+
+use super::line::Command;
+use super::value::{Value, ValueType};
+
+/// What happened when a line ran.
+pub struct TraceEvent {
+    pub file: String,
+    pub line_number: usize,
+    /// The source text of the line without the indentation.
+    pub source: String,
+    pub command: Command,
+    /// The label, for, each or function line the line comes after, if there is one.
+    pub label: Option<String>,
+    /// The functions that were called and haven't returned, the innermost last.
+    pub functions: Vec<String>,
+    /// The variables the line read with the values they had, each only once.
+    pub arguments: Vec<(String, Value)>,
+    /// The variables the line assigned with their new values, in order.
+    pub assignments: Vec<(String, Value)>,
+    /// How long the line took to run, including garbage collection.
+    pub duration: Duration,
+    /// Tables, dictionaries and lists the line made.
+    pub tables_created: u64,
+    /// How long garbage collection paused the line for.
+    pub garbage_collection: Duration,
+    /// For if lines whether the condition was true, and for for lines whether the loop was entered.
+    pub branch: Option<bool>,
+}
+
+/// Is told about every line the VM runs.
+pub trait TraceHook {
+    fn trace(&mut self, event: &TraceEvent);
+
+    /// Called when the program stops running, to write out what was collected.
+    fn finish(&mut self) {}
+}
+
+/// Which lines are traced. Lines are traced if they come after one of the labels or run inside
+/// one of the functions, or always if there are neither.
+#[derive(Default)]
+pub struct TraceFilter {
+    pub labels: Vec<String>,
+    pub functions: Vec<String>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, event: &TraceEvent) -> bool {
+        if self.labels.is_empty() && self.functions.is_empty() {
+            return true;
+        }
+
+        event.label.as_ref().is_some_and(|label| self.labels.contains(label))
+            || event.functions.iter().any(|function| self.functions.contains(function))
+    }
+}
+
+/// Writes a line of text for every traced line, like
+/// `program.kdt:12: sum n * 2 | reads n = 3 | sets @save = 6`.
+pub struct TextTracer {
+    output: Box<dyn Write>,
+    filter: TraceFilter,
+}
+
+impl TextTracer {
+    pub fn new(output: Box<dyn Write>, filter: TraceFilter) -> TextTracer {
+        TextTracer { output, filter }
+    }
+}
+
+impl TraceHook for TextTracer {
+    fn trace(&mut self, event: &TraceEvent) {
+        if !self.filter.matches(event) {
+            return;
+        }
+
+        let mut text = format!("{}:{}: {}", event.file, event.line_number, event.source.replace('\n', " "));
+
+        let describe = |variables: &[(String, Value)]| variables.iter()
+            .map(|(name, value)| format!("{} = {}", name, value_to_text(value)))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        if !event.arguments.is_empty() {
+            text.push_str(&format!(" | reads {}", describe(&event.arguments)));
+        }
+
+        if !event.assignments.is_empty() {
+            text.push_str(&format!(" | sets {}", describe(&event.assignments)));
+        }
+
+        writeln!(self.output, "{}", text).ok();
+    }
+
+    fn finish(&mut self) {
+        self.output.flush().ok();
+    }
+}
+
+/// Writes a JSON object on its own line for every traced line, for tools to read.
+pub struct JsonTracer {
+    output: Box<dyn Write>,
+    filter: TraceFilter,
+}
+
+impl JsonTracer {
+    pub fn new(output: Box<dyn Write>, filter: TraceFilter) -> JsonTracer {
+        JsonTracer { output, filter }
+    }
+}
+
+impl TraceHook for JsonTracer {
+    fn trace(&mut self, event: &TraceEvent) {
+        if !self.filter.matches(event) {
+            return;
+        }
+
+        let variables = |variables: &[(String, Value)]| variables.iter()
+            .map(|(name, value)| format!("{{\"name\":{},\"value\":{}}}", json_string(name), value_to_json(value)))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        writeln!(self.output, "{{\"file\":{},\"line\":{},\"source\":{},\"label\":{},\"functions\":[{}],\"arguments\":[{}],\"assignments\":[{}]}}",
+            json_string(&event.file),
+            event.line_number,
+            json_string(&event.source),
+            event.label.as_deref().map(json_string).unwrap_or("null".to_string()),
+            event.functions.iter().map(|function| json_string(function)).collect::<Vec<String>>().join(","),
+            variables(&event.arguments),
+            variables(&event.assignments),
+        ).ok();
+    }
+
+    fn finish(&mut self) {
+        self.output.flush().ok();
+    }
+}
+
+/// Tables, dictionaries and lists are shown by their id since their contents can be large.
+fn value_to_text(value: &Value) -> String {
+    match value.value_type {
+        ValueType::Number => value.number_to_string(),
+        ValueType::String => format!("{:?}", value.string_value.as_deref().unwrap_or("")),
+        ValueType::Table if value.is_uninitialized() => "empty table".to_string(),
+        ValueType::Table if value.slice.is_some() => format!("slice of table {}", value.table_index),
+        ValueType::Table => format!("table {}", value.table_index),
+        ValueType::Dictionary => format!("dictionary {}", value.table_index),
+        ValueType::List => format!("list {}", value.table_index),
+    }
+}
+
+fn value_to_json(value: &Value) -> String {
+    match value.value_type {
+        ValueType::Number if value.integer_value.is_some() || value.number_value.is_finite() => value.number_to_string(),
+        ValueType::Number => json_string(&value.number_to_string()),
+        ValueType::String => json_string(value.string_value.as_deref().unwrap_or("")),
+        ValueType::Table if value.is_uninitialized() => "{\"table\":null}".to_string(),
+        ValueType::Table => format!("{{\"table\":{},\"slice\":{}}}", value.table_index, value.slice.is_some()),
+        ValueType::Dictionary => format!("{{\"dictionary\":{}}}", value.table_index),
+        ValueType::List => format!("{{\"list\":{}}}", value.table_index),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
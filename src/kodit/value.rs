@@ -1,10 +1,18 @@
+use std::cmp::Ordering;
 use std::fmt::{Display, Error};
 
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use super::table::TableSlice;
+
 #[derive(Clone)]
 pub enum ValueType {
     String,
     Number,
     Table,
+    Dictionary,
+    List,
 }
 
 #[derive(Clone)]
@@ -12,8 +20,12 @@ pub struct Value {
     pub value_type: ValueType,
     pub string_value: Option<String>,
     pub number_value: f64,
+    /// The exact value of an integral number, in which case the float value is its closest approximation.
+    pub integer_value: Option<BigInt>,
+    /// The id of the table, dictionary or list in the table manager.
     pub table_index: usize,
-    pub slice_offset: Option<Vec<usize>>,
+    /// The part of the table the value refers to, or the whole table if not set.
+    pub slice: Option<TableSlice>,
 }
 
 impl Value {
@@ -22,8 +34,9 @@ impl Value {
             value_type: ValueType::Table,
             string_value: None,
             number_value: 0f64,
+            integer_value: None,
             table_index: usize::MAX,
-            slice_offset: None,
+            slice: None,
         }
     }
 
@@ -32,8 +45,20 @@ impl Value {
             value_type: ValueType::Number,
             string_value: None,
             number_value: n,
+            integer_value: None,
             table_index: 0,
-            slice_offset: None,
+            slice: None,
+        }
+    }
+
+    pub fn integer_value(n: BigInt) -> Value {
+        Value {
+            value_type: ValueType::Number,
+            string_value: None,
+            number_value: n.to_f64().unwrap_or(f64::NAN),
+            integer_value: Some(n),
+            table_index: 0,
+            slice: None,
         }
     }
 
@@ -43,19 +68,36 @@ impl Value {
             value_type: ValueType::String,
             string_value: Some(string.to_owned()),
             number_value: 0f64,
+            integer_value: None,
             table_index: 0,
-            slice_offset: None,
+            slice: None,
         }
     }
 
     pub fn boolean_value(b: bool) -> Value {
-        Value::number_value(if b {1.0} else {0.0})
+        Value::integer_value(BigInt::from(if b {1} else {0}))
     }
 
     pub fn as_boolean(&self) -> bool {
         !matches!(self.value_type, ValueType::Number) || self.number_value != 0.0
     }
 
+    /// Integers are compared exactly, otherwise the numbers are compared as floats.
+    pub fn compare_numbers(&self, other: &Value) -> Option<Ordering> {
+        match (&self.integer_value, &other.integer_value) {
+            (Some(a), Some(b)) => Some(a.cmp(b)),
+            _ => self.number_value.partial_cmp(&other.number_value),
+        }
+    }
+
+    /// Integers are written with all of their digits.
+    pub fn number_to_string(&self) -> String {
+        match &self.integer_value {
+            Some(n) => n.to_string(),
+            None => self.number_value.to_string(),
+        }
+    }
+
     pub fn is_uninitialized(&self) -> bool {
         self.table_index == usize::MAX && matches!(self.value_type, ValueType::Table)
     }
@@ -64,12 +106,14 @@ impl Value {
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.value_type {
-            ValueType::Number => write!(f, "<Number Value {}>", self.number_value),
+            ValueType::Number => write!(f, "<Number Value {}>", self.number_to_string()),
             ValueType::String => write!(f, "<String Value {}>", match self.string_value.as_ref() {
                 Some(s) => s,
                 None => return Err(Error),
             }),
             ValueType::Table => write!(f, "<Table Value>"),
+            ValueType::Dictionary => write!(f, "<Dictionary Value>"),
+            ValueType::List => write!(f, "<List Value>"),
         }
     }
 }
\ No newline at end of file
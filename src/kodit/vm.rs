@@ -1,9 +1,24 @@
 use core::panic;
 use std::collections::HashMap;
-use std::io::{self, Write};
+use std::collections::hash_map::Entry;
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
 
 use super::environment::{Environment, Label};
 use super::line::{Command, Line, LineItem};
+use super::math;
+use super::expression::Expression;
+use super::random::Random;
+use super::file_system::FileSystemPolicy;
+use super::limits::Limits;
+use super::io_backend::{IoBackend, StdIo};
+use super::trace::{TraceEvent, TraceHook};
 use super::value::{Value, ValueType};
 
 use super::table::*;
@@ -13,6 +28,38 @@ pub struct VM {
     pub labels: LabelMap,
     pub table_manager: TableManager,
     pub table_creations: u32,
+    /// Parsed expressions of rpn and let lines by line index.
+    pub expressions: HashMap<usize, Expression>,
+    pub random: Random,
+    /// The variables the env command can read, the process environment by default. Names and
+    /// values that aren't UTF-8 have their invalid bytes replaced.
+    pub environment_variables: HashMap<String, String>,
+    /// What the file command can access. Nothing until it is set.
+    pub file_system: FileSystemPolicy,
+    pub limits: Limits,
+    pub executed_lines: u64,
+    /// Where say writes and ask reads, the standard output and input by default.
+    pub io: Box<dyn IoBackend>,
+    pub file: String,
+    pub code: Rc<Vec<Line>>,
+    /// The index of the line that runs next.
+    pub program_counter: usize,
+    pub status: Status,
+    /// When the program was loaded, for the time limit.
+    pub started: Instant,
+    /// Told about every line that runs.
+    pub tracers: Vec<Box<dyn TraceHook>>,
+    pub garbage_collection_time: Duration,
+    /// The name of the closest label, for, each or function line at or before every line, for tracing.
+    section_labels: Vec<Option<String>>,
+    /// The arguments given to the program, put into @args whenever the variables start over.
+    arguments: Vec<String>,
+    /// The limit a command of the running line went over, reported instead of the error it gave.
+    exceeded_limit: Option<String>,
+    /// The variables the running line read and assigned, only recorded while tracing.
+    traced_reads: RefCell<Vec<(String, Value)>>,
+    traced_assignments: Vec<(String, Value)>,
+    traced_branch: Option<bool>,
 }
 
 pub struct LabelMap(HashMap<String, Label>);
@@ -29,11 +76,77 @@ impl LabelMap {
         self.0.get(label_name)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Label)> {
+        self.0.iter()
+    }
+
     pub fn insert(&mut self, label_name: String, value: Label) {
         self.0.insert(label_name, value);
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorKind {
+    /// The program did something wrong.
+    Error,
+    /// The program went over one of the limits of the VM.
+    LimitExceeded,
+    /// The values of an assert line weren't equal.
+    AssertionFailed,
+}
+
+/// Where a program is after running some of its lines.
+#[derive(Debug, Clone)]
+pub enum Status {
+    /// There are more lines to run.
+    Running,
+    /// An ask line is waiting for a line of input from the IO backend.
+    WaitingForInput,
+    /// The program ended with this exit code.
+    Finished(i32),
+    Error(RuntimeError),
+}
+
+/// An error that stops the program, with the line of the source file it happened at.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub line_number: usize,
+    pub message: String,
+    pub kind: RuntimeErrorKind,
+}
+
+impl RuntimeError {
+    pub fn new(line_number: usize, message: String) -> RuntimeError {
+        RuntimeError { line_number, message, kind: RuntimeErrorKind::Error }
+    }
+
+    pub fn limit_exceeded(line_number: usize, message: String) -> RuntimeError {
+        RuntimeError { line_number, message, kind: RuntimeErrorKind::LimitExceeded }
+    }
+
+    pub fn assertion_failed(line_number: usize, message: String) -> RuntimeError {
+        RuntimeError { line_number, message, kind: RuntimeErrorKind::AssertionFailed }
+    }
+
+    /// The exit code of a program stopped by the error.
+    pub fn exit_code(&self) -> i32 {
+        match self.kind {
+            RuntimeErrorKind::Error | RuntimeErrorKind::AssertionFailed => 1,
+            RuntimeErrorKind::LimitExceeded => 2,
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            RuntimeErrorKind::Error => write!(f, "Error at line {}: {}", self.line_number, self.message),
+            RuntimeErrorKind::LimitExceeded => write!(f, "Limit exceeded at line {}: {}", self.line_number, self.message),
+            RuntimeErrorKind::AssertionFailed => write!(f, "Assertion failed at line {}: {}", self.line_number, self.message),
+        }
+    }
+}
+
 impl VM {
     pub fn new() -> Self {
         VM {
@@ -41,364 +154,900 @@ impl VM {
             labels: LabelMap(HashMap::new()),
             table_manager: TableManager::new(),
             table_creations: 0,
+            expressions: HashMap::new(),
+            random: Random::from_time(),
+            environment_variables: std::env::vars_os()
+                .map(|(name, value)| (name.to_string_lossy().into_owned(), value.to_string_lossy().into_owned()))
+                .collect(),
+            file_system: FileSystemPolicy::disabled(),
+            limits: Limits::default(),
+            executed_lines: 0,
+            io: Box::new(StdIo),
+            file: String::new(),
+            code: Rc::new(vec!()),
+            program_counter: 0,
+            status: Status::Running,
+            started: Instant::now(),
+            tracers: vec!(),
+            garbage_collection_time: Duration::ZERO,
+            section_labels: vec!(),
+            arguments: vec!(),
+            exceeded_limit: None,
+            traced_reads: RefCell::new(vec!()),
+            traced_assignments: vec!(),
+            traced_branch: None,
+        }
+    }
+
+    /// Makes the arguments available to the program as a one-dimensional table of strings in @args.
+    pub fn set_arguments(&mut self, arguments: &[String]) {
+        self.arguments = arguments.to_vec();
+        self.define_arguments();
+    }
+
+    fn define_arguments(&mut self) {
+        let table = self.table_manager.create_table(&vec![self.arguments.len()]);
+        let values = self.arguments.iter().map(Value::string_value).collect();
+        self.table_manager.write_raw(&table, &values).unwrap();
+        self.environment[0].variables.insert("@args".to_string(), table);
+    }
+
+    pub fn set_environment_variable(&mut self, name: &str, value: &str) {
+        self.environment_variables.insert(name.to_string(), value.to_string());
+    }
+
+    /// Makes the random command give the same numbers on every run.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    /// Runs the program and gives its exit status, which is the code of an exit command or
+    /// the number in @save when the program runs past its last line.
+    pub fn evaluate(&mut self, file: &String, code: Vec<Line>) -> Result<i32, RuntimeError> {
+        self.load(file, code)?;
+        self.run_to_end()
+    }
+
+    /// Runs the loaded program until it finishes or fails.
+    pub fn run_to_end(&mut self) -> Result<i32, RuntimeError> {
+        loop {
+            match self.run_for(u64::MAX) {
+                Status::Running => (),
+                Status::WaitingForInput => return Err(RuntimeError::new(self.source_line_number(), "The program is waiting for input but there is none.".to_string())),
+                Status::Finished(exit_code) => return Ok(exit_code),
+                Status::Error(e) => return Err(e),
+            }
         }
     }
 
-    pub fn evaluate(&mut self, file: &String, code: &Vec<Line>) {
-        let mut current_line_number = 0;
+    /// Gets the program ready to run from its first line. The host can then run it a few
+    /// lines at a time with step and run_for. The labels, variables and tables of a program
+    /// loaded before are forgotten, but the arguments stay.
+    pub fn load(&mut self, file: &String, code: Vec<Line>) -> Result<(), RuntimeError> {
+        self.labels = LabelMap(HashMap::new());
+        self.expressions.clear();
+        self.environment = vec![Environment::that_returns_to(None)];
+        self.table_manager = TableManager::new();
+        self.table_creations = 0;
+        self.executed_lines = 0;
+        self.define_arguments();
 
         // Record all the label positions.
-        for current_line in code {
+        for (current_line_number, current_line) in code.iter().enumerate() {
             match &current_line.items[0] {
                 LineItem::Command(command) => match command {
-                    Command::FOR | Command::LABEL | Command::FUNCTION => self.add_label(match &current_line.items[1] {
+                    Command::FOR | Command::EACH | Command::LABEL | Command::FUNCTION => self.add_label(match &current_line.items[1] {
                         LineItem::Label(label) => label.to_owned(),
-                        _ => panic!("Not valid "),
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Labels must be names.".to_string())),
                     }, file.to_owned(), current_line_number),
                     _ => (),
                 },
-                _ => panic!("Line parsing error: The first item in the line is supposed to be a command."),
+                _ => return Err(RuntimeError::new(current_line.line_number, "Line parsing error: The first item in the line is supposed to be a command.".to_string())),
             }
+        }
+
+        let mut section = None;
+        self.section_labels = code.iter().map(|line| {
+            if let (LineItem::Command(Command::LABEL | Command::FOR | Command::EACH | Command::FUNCTION), Some(LineItem::Label(label))) = (&line.items[0], line.items.get(1)) {
+                section = Some(label.clone());
+            }
+            section.clone()
+        }).collect();
+
+        self.file = file.to_owned();
+        self.code = Rc::new(code);
+        self.program_counter = 0;
+        self.status = Status::Running;
+        self.started = Instant::now();
+
+        Ok(())
+    }
+
+    /// Gets the VM ready to run a function of the loaded program on its own, the way test
+    /// functions are run. The function starts in a fresh environment without the variables and
+    /// tables of earlier runs and takes no arguments, but @args holds the arguments of the
+    /// program. None of the lines outside of the function run first, so the variables they
+    /// would set don't exist. The program ends when the function returns.
+    pub fn start_function(&mut self, name: &str) -> Result<(), String> {
+        let line_index = match self.labels.get(name) {
+            Some(label) => label.line_number,
+            None => return Err(format!("Function {} not known.", name)),
+        };
 
-            current_line_number += 1;
+        let function_line = &self.code[line_index];
+        if !matches!(function_line.items[0], LineItem::Command(Command::FUNCTION)) {
+            return Err(format!("{} is not a function.", name));
+        }
+        if function_line.items.len() > 2 {
+            return Err(format!("Function {} takes arguments so it cannot be run on its own.", name));
         }
 
-        current_line_number = 0;
+        // Returning goes past the last line, which ends the program.
+        let mut function = Environment::that_returns_to(Some(Label { file: self.file.clone(), line_number: self.code.len() }));
+        function.function = Some(name.to_string());
 
-        // Execute.
-        while current_line_number < code.len() {
-            let current_line = &code[current_line_number];
+        self.environment = vec![Environment::that_returns_to(None), function];
+        self.table_manager = TableManager::new();
+        self.table_creations = 0;
+        self.define_arguments();
+        self.executed_lines = 0;
+        self.program_counter = line_index;
+        self.status = Status::Running;
+        self.started = Instant::now();
 
-            match &current_line.items[0] {
-                LineItem::Command(command) => match command {
-                    Command::LABEL => {
-                        current_line_number += 1;
-                    },
-                    Command::FUNCTION => {
-                        current_line_number += 1;
-                    },
-                    Command::CALL => {
-                        let label_text = match &current_line.items[1] {
-                            LineItem::Label(label) | LineItem::String(label) => label, // Rust is cool!
-                            _ => panic!("Error at line {}: Cannot call function with a non-label and non-string qualifier.", current_line.line_number),
-                        };
-                        let jump_target = match self.labels.get(label_text) {
-                            Some(l) => l,
-                            None => panic!("Error at line {}: Function not known.", current_line.line_number),
-                        };
-                        match self.call_function(&code[jump_target.line_number], current_line, file, jump_target.line_number, current_line_number) {
-                            Ok(l) => current_line_number = l.line_number,
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
-                    },
-                    Command::RETURN => {
-                        current_line_number = match self.return_from_function(current_line, current_line_number) {
-                            Ok(l) => l.line_number,
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        }
-                    },
-                    Command::GOTO => {
-                        let label_text = match &current_line.items[1] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot go to non-label.", current_line.line_number),
-                        };
+        Ok(())
+    }
 
-                        current_line_number = match self.labels.get_line_number_with_inferred(label_text, &current_line_number) {
-                            Some(n) if n < code.len() => n,
-                            _ => panic!("Error at line {}: Label not known.", current_line_number),
-                        };
-                    }
-                    Command::IF => {
-                        let cond = match self.line_item_as_value(&current_line.items[1]) {
-                            Ok(v) => v.clone(),
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
+    /// Runs one line. A program that has finished or failed keeps giving the same status, and
+    /// one that is waiting for input tries to read it again.
+    pub fn step(&mut self) -> Status {
+        if matches!(self.status, Status::Finished(_) | Status::Error(_)) {
+            return self.status.clone();
+        }
 
-                        let which_label = if cond.as_boolean() {2usize} else {3usize};
-                        let label_text = match &current_line.items[which_label] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot go to non-label.", current_line.line_number),
-                        };
+        if self.program_counter < self.code.len() {
+            self.status = match self.execute_traced_line() {
+                Ok(status) => status,
+                Err(e) => Status::Error(match self.exceeded_limit.take() {
+                    Some(message) => RuntimeError::limit_exceeded(e.line_number, message),
+                    None => e,
+                }),
+            };
+        }
 
-                        current_line_number = match self.labels.get_line_number_with_inferred(label_text, &current_line_number) {
-                            Some(n) if n < code.len() => n,
-                            _ => panic!("Error at line {}: Label not known.", current_line_number),
-                        };
-                    }
-                    Command::FOR => {
-                        let end_value = match self.line_item_as_value(&current_line.items[4]) {
-                            Ok(v) => v,
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
+        if matches!(self.status, Status::Running) && self.program_counter >= self.code.len() {
+            self.status = Status::Finished(VM::implicit_exit_code(self.read_variable(&"@save".to_string())));
+        }
 
-                        let label_end_text = match &current_line.items[2] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot go to non-label.", current_line.line_number),
-                        };
+        self.status.clone()
+    }
 
-                        let label_end_line_number = match self.labels.get_line_number_with_inferred(label_end_text, &current_line_number) {
-                            Some(n) if n < code.len() => n,
-                            _ => panic!("Error at line {}: Label not known.", current_line_number),
-                        };
+    /// Runs up to the given number of lines, stopping early when the program finishes, fails
+    /// or has to wait for input.
+    pub fn run_for(&mut self, steps: u64) -> Status {
+        let mut status = self.status.clone();
 
-                        let variable_name = match &current_line.items[3] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Loop variable invalid.", current_line.line_number),
-                        };
+        for _ in 0..steps {
+            status = self.step();
+            if !matches!(status, Status::Running) {
+                break;
+            }
+        }
 
-                        let value = match self.read_variable(variable_name) {
-                            Some(v) => v,
-                            None => panic!("Error at line {}: For loop variable not found.", current_line.line_number),
-                        };
+        status
+    }
 
-                        let current = match value.value_type {
-                            ValueType::Number => value.number_value,
-                            _ => panic!("Error at line {}: Can only iterate with numbers.", current_line.line_number),
-                        };
+    /// The line number in the source file of the line that runs next.
+    pub fn source_line_number(&self) -> usize {
+        self.code.get(self.program_counter).map(|line| line.line_number).unwrap_or(0)
+    }
 
-                        let compare = match end_value.value_type {
-                            ValueType::Number => end_value.number_value,
-                            _ => panic!("Error at line {}: Can only iterate with numbers.", current_line.line_number),
-                        };
+    /// Runs the line, telling the tracers about it if there are any. Lines waiting for input
+    /// are told about once the input has come.
+    fn execute_traced_line(&mut self) -> Result<Status, RuntimeError> {
+        if self.tracers.is_empty() {
+            return self.execute_line();
+        }
 
-                        if current < compare {
-                            current_line_number += 1;
-                        } else {
-                            current_line_number = label_end_line_number;
-                        }
-                    }
-                    Command::CONTINUE => {
-                        let label_text = match &current_line.items[1] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot go to non-label.", current_line.line_number),
-                        };
+        let line = &self.code[self.program_counter];
+        let mut event = TraceEvent {
+            file: self.file.clone(),
+            line_number: line.line_number,
+            source: line.source.trim().to_string(),
+            command: match &line.items[0] {
+                LineItem::Command(command) => command.clone(),
+                _ => Command::NOOP,
+            },
+            label: self.section_label(self.program_counter),
+            functions: self.environment.iter().filter_map(|environment| environment.function.clone()).collect(),
+            arguments: vec!(),
+            assignments: vec!(),
+            duration: Duration::ZERO,
+            tables_created: 0,
+            garbage_collection: Duration::ZERO,
+            branch: None,
+        };
 
-                        let label_line_number = match self.labels.get_line_number_with_inferred(label_text, &current_line_number) {
-                            Some(n) if n < code.len() => n,
-                            _ => panic!("Error at line {}: Label not known.", current_line_number),
-                        };
+        self.traced_reads.borrow_mut().clear();
+        self.traced_assignments.clear();
+        self.traced_branch = None;
 
-                        current_line_number = label_line_number;
+        let tables_created = self.table_manager.created();
+        let garbage_collection_time = self.garbage_collection_time;
+        let started = Instant::now();
 
-                        let current_line = &code[current_line_number];
+        let result = self.execute_line();
 
-                        if !matches!(current_line.items[0], LineItem::Command(Command::FOR)) {
-                            panic!("Error at line {}: Can only continue to a for line.", label_line_number);
-                        }
+        event.duration = started.elapsed();
+        event.tables_created = self.table_manager.created() - tables_created;
+        event.garbage_collection = self.garbage_collection_time - garbage_collection_time;
 
-                        let variable_name = match &current_line.items[3] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot go to non-label.", current_line.line_number),
-                        };
+        if matches!(result, Ok(Status::WaitingForInput)) {
+            return result;
+        }
 
-                        let value = match self.read_variable(variable_name) {
-                            Some(v) => v,
-                            None => panic!("Error at line {}: For loop variable not found.", label_line_number),
-                        };
+        for (name, value) in self.traced_reads.take() {
+            if !event.arguments.iter().any(|(read, _)| *read == name) {
+                event.arguments.push((name, value));
+            }
+        }
+        event.assignments = std::mem::take(&mut self.traced_assignments);
+        event.branch = self.traced_branch;
 
-                        let new_value = match value.value_type {
-                            ValueType::Number => value.number_value,
-                            _ => panic!("Error at line {}: Can only iterate with numbers.", current_line.line_number),
-                        }.round() as i32 + 1;
+        for tracer in &mut self.tracers {
+            tracer.trace(&event);
+        }
 
-                        self.assign_variable(variable_name, Value::number_value(new_value as f64));
-                    }
-                    Command::SUM => {
-                        let operand = match &current_line.items[2] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Operand must be a label.", current_line.line_number),
-                        };
-                        let v1 = match self.line_item_as_value(&current_line.items[1]) {
-                            Ok(v) => v,
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
-                        let v2 = match self.line_item_as_value(&current_line.items[3]) {
-                            Ok(v) => v,
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
-                        let sum = match VM::do_sum(&v1, &v2, &operand) {
-                            Ok(v) => v,
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
+        result
+    }
 
-                        self.assign_variable(&"@save".to_string(), sum);
-                        current_line_number += 1;
+    /// The name of the closest label, for, each or function line at or before the line.
+    fn section_label(&self, line_index: usize) -> Option<String> {
+        self.section_labels.get(line_index).cloned().flatten()
+    }
+
+    fn execute_line(&mut self) -> Result<Status, RuntimeError> {
+        self.table_manager.end_line();
+        let code = Rc::clone(&self.code);
+        let mut current_line_number = self.program_counter;
+        let current_line = &code[current_line_number];
+
+        // Checking for input again doesn't count as running the line again.
+        if !matches!(self.status, Status::WaitingForInput) {
+            if let Some(message) = self.check_limits() {
+                return Err(RuntimeError::limit_exceeded(current_line.line_number, message));
+            }
+        }
+
+        match &current_line.items[0] {
+            LineItem::Command(command) => match command {
+                Command::LABEL => {
+                    current_line_number += 1;
+                },
+                Command::FUNCTION => {
+                    current_line_number += 1;
+                },
+                Command::CALL => {
+                    let label_text = match &current_line.items[1] {
+                        LineItem::Label(label) | LineItem::String(label) => label, // Rust is cool!
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot call function with a non-label and non-string qualifier.".to_string())),
+                    };
+                    let jump_target = match self.labels.get(label_text) {
+                        Some(l) => l,
+                        None => return Err(RuntimeError::new(current_line.line_number, "Function not known.".to_string())),
+                    };
+                    let file = self.file.clone();
+                    match self.call_function(&code[jump_target.line_number], current_line, &file, jump_target.line_number, current_line_number) {
+                        Ok(l) => current_line_number = l.line_number,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+                },
+                Command::RETURN => {
+                    current_line_number = match self.return_from_function(current_line) {
+                        Ok(l) => l.line_number,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    }
+                },
+                Command::GOTO => {
+                    let label_text = match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot go to non-label.".to_string())),
+                    };
+
+                    current_line_number = match self.labels.get_line_number_with_inferred(label_text, &current_line_number) {
+                        Some(n) if n < code.len() => n,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Label not known.".to_string())),
+                    };
+                }
+                Command::IF => {
+                    let cond = match self.line_item_as_value(&current_line.items[1]) {
+                        Ok(v) => v.clone(),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+
+                    self.traced_branch = Some(cond.as_boolean());
+                    let which_label = if cond.as_boolean() {2usize} else {3usize};
+                    let label_text = match &current_line.items[which_label] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot go to non-label.".to_string())),
+                    };
+
+                    current_line_number = match self.labels.get_line_number_with_inferred(label_text, &current_line_number) {
+                        Some(n) if n < code.len() => n,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Label not known.".to_string())),
+                    };
+                }
+                Command::FOR => {
+                    let end_value = match self.line_item_as_value(&current_line.items[4]) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+
+                    let label_end_text = match &current_line.items[2] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot go to non-label.".to_string())),
+                    };
+
+                    let label_end_line_number = match self.labels.get_line_number_with_inferred(label_end_text, &current_line_number) {
+                        Some(n) if n < code.len() => n,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Label not known.".to_string())),
+                    };
+
+                    let variable_name = match &current_line.items[3] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Loop variable invalid.".to_string())),
+                    };
+
+                    let value = match self.read_variable(variable_name) {
+                        Some(v) => v,
+                        None => return Err(RuntimeError::new(current_line.line_number, "For loop variable not found.".to_string())),
+                    };
+
+                    if !matches!((&value.value_type, &end_value.value_type), (ValueType::Number, ValueType::Number)) {
+                        return Err(RuntimeError::new(current_line.line_number, "Can only iterate with numbers.".to_string()));
                     }
-                    Command::SAY => {
-                        let str = self.line_item_to_string(&current_line.items[1], &current_line.line_number);
-                        self.say(str);
-                        current_line_number += 1;
-                    },
-                    Command::ASK => {
-                        // flush the output also so the user is not confused.
-                        let str = self.line_item_to_string(&current_line.items[1], &current_line.line_number);
-                        self.say(str);
-                        io::stdout().flush().unwrap();
 
-                        let mut buf = String::new();
-                        io::stdin().read_line(&mut buf).unwrap();
+                    let current = value.clone();
 
-                        // pop the trailing newline in the string.
-                        let trimmed = buf.strip_suffix("\r\n")
-                            .or(buf.strip_suffix("\n"))
-                            .unwrap_or(buf.as_str());
+                    let step = match self.for_step(current_line) {
+                        Ok(s) => s,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
 
-                        self.assign_variable(&"@save".to_string(), Value {
-                            value_type: ValueType::String,
-                            string_value: Some(trimmed.to_string()),
-                            number_value: 0f64,
-                            table_index: 0,
-                            slice_offset: None,
-                        });
+                    let keep_going = current.compare_numbers(&end_value) == Some(if step.number_value > 0.0 { Ordering::Less } else { Ordering::Greater });
+                    self.traced_branch = Some(keep_going);
 
+                    if keep_going {
                         current_line_number += 1;
+                    } else {
+                        current_line_number = label_end_line_number;
                     }
-                    Command::SET => {
-                        let value = match self.line_item_as_value(&current_line.items[2]) {
-                            Ok(v) => v.clone(),
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
+                }
+                Command::CONTINUE => {
+                    let label_text = match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot go to non-label.".to_string())),
+                    };
 
-                        self.assign_variable(match &current_line.items[1] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot assign to such variable.", current_line.line_number),
-                        }, value);
+                    let label_line_number = match self.labels.get_line_number_with_inferred(label_text, &current_line_number) {
+                        Some(n) if n < code.len() => n,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Label not known.".to_string())),
+                    };
 
-                        current_line_number += 1;
-                    }
-                    Command::TABLE => {
-                        let dimensions = current_line.items[2..].iter().map(
-                            |item| match self.line_item_as_value(item) {
-                                Ok(value) => match value.value_type {
-                                    ValueType::Number => value.number_value.round() as usize,
-                                    _ => panic!("Error at line {}: Invalid dimension. Each dimension must be a number.", current_line.line_number)
-                                },
-                                Err(e) => panic!("Error at line {}: Invalid dimension. {}", current_line.line_number, e),
-                            }
-                        ).collect::<Vec<usize>>();
-
-                        self.create_table(match &current_line.items[1] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot assign to such variable.", current_line.line_number),
-                        }, &dimensions);
-                        current_line_number += 1;
-                    },
-                    Command::GET => {
-                        let variable_name = match &current_line.items[1] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot read such variable.", current_line.line_number),
+                    current_line_number = label_line_number;
+
+                    let current_line = &code[current_line_number];
+
+                    if matches!(current_line.items[0], LineItem::Command(Command::EACH)) {
+                        let position = match self.environment.last().unwrap().iterators.get(&label_line_number) {
+                            Some(p) => *p,
+                            None => return Err(RuntimeError::new(current_line.line_number, "Cannot continue an each loop that isn't running.".to_string())),
                         };
-                        let table = match self.read_variable(variable_name) {
-                            Some(v) => match v.value_type {
-                                ValueType::Table => v,
-                                _ => panic!("Error at line {}: Variable is not a table.", current_line.line_number),
+
+                        current_line_number = match self.each_step(current_line, label_line_number, position) {
+                            Ok(true) => label_line_number + 1,
+                            Ok(false) => match self.each_end(current_line, label_line_number, code.len()) {
+                                Ok(n) => n,
+                                Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
                             },
-                            None => panic!("Error at line {}: Variable {} not found.", current_line.line_number, variable_name),
+                            Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
                         };
-                        let dimensions = current_line.items[2..].iter().map(
-                            |item| match self.line_item_as_value_pure(item) {
-                                Ok(value) => match value.value_type {
-                                    ValueType::Number => value.number_value.round() as usize,
-                                    _ => panic!("Error at line {}: Invalid dimension. Each dimension must be a number.", current_line.line_number)
-                                },
-                                Err(e) => panic!("Error at line {}: Invalid dimension. {}", current_line.line_number, e),
-                            }
-                        ).collect::<Vec<usize>>();
-                        let value = match self.table_manager.get(table, dimensions) {
-                            Ok(v) => v,
-                            Err(e) => panic!("Error at line {}: Error reading from table. {}", current_line.line_number, e),
-                        }.clone();
-                        self.assign_variable(&"@save".to_string(), value);
-                        current_line_number += 1;
+                        self.program_counter = current_line_number;
+                        return Ok(Status::Running);
                     }
-                    Command::PUT => {
-                        let variable_name = match &current_line.items[1] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot read such variable.", current_line.line_number),
+
+                    if !matches!(current_line.items[0], LineItem::Command(Command::FOR)) {
+                        return Err(RuntimeError::new(label_line_number, "Can only continue to a for or each line.".to_string()));
+                    }
+
+                    let variable_name = match &current_line.items[3] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot go to non-label.".to_string())),
+                    };
+
+                    let value = match self.read_variable(variable_name) {
+                        Some(v) => v,
+                        None => return Err(RuntimeError::new(label_line_number, "For loop variable not found.".to_string())),
+                    };
+
+                    let current = match value.value_type {
+                        ValueType::Number => value.clone(),
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Can only iterate with numbers.".to_string())),
+                    };
+
+                    let step = match self.for_step(current_line) {
+                        Ok(s) => s,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+
+                    let next = match VM::do_number_operation(&current, &step, "+") {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+                    self.assign_variable(variable_name, next);
+                }
+                Command::EACH => {
+                    current_line_number = match self.each_step(current_line, current_line_number, 0) {
+                        Ok(true) => current_line_number + 1,
+                        Ok(false) => match self.each_end(current_line, current_line_number, code.len()) {
+                            Ok(n) => n,
+                            Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
+                        },
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+                }
+                Command::SUM => {
+                    let operand = match &current_line.items[2] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Operand must be a label.".to_string())),
+                    };
+                    let v1 = match self.line_item_as_value(&current_line.items[1]) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+                    let v2 = match self.line_item_as_value(&current_line.items[3]) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+                    let sum = match VM::do_sum(&v1, &v2, &operand) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+
+                    self.assign_variable(&"@save".to_string(), sum);
+                    current_line_number += 1;
+                }
+                Command::SAY => {
+                    let str = match self.line_item_to_string(&current_line.items[1]) {
+                        Ok(s) => s,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
+                    };
+                    self.say(str);
+                    current_line_number += 1;
+                },
+                Command::ASK => {
+                    // The prompt is shown once, not every time the input is checked for again.
+                    if !matches!(self.status, Status::WaitingForInput) {
+                        let str = match self.line_item_to_string(&current_line.items[1]) {
+                            Ok(s) => s,
+                            Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
                         };
-                        let table = match self.read_variable(variable_name) {
-                            Some(v) => {match v.value_type {
-                                ValueType::Table => v,
-                                _ => panic!("Error at line {}: Variable is not a table.", current_line.line_number),
-                            }},
-                            None => panic!("Error at line {}: Variable {} not found.", current_line.line_number, variable_name),
-                        }.to_owned();
-                        let table_dimensions = match self.table_manager.get_dimensions(&table) {
+                        self.say(str);
+                        // flush the output also so the user is not confused.
+                        self.io.flush();
+                    }
+
+                    let input = match self.io.read_line() {
+                        Some(line) => line,
+                        None => return Ok(Status::WaitingForInput),
+                    };
+
+                    self.assign_variable(&"@save".to_string(), Value {
+                        value_type: ValueType::String,
+                        string_value: Some(input),
+                        number_value: 0f64,
+                        integer_value: None,
+                        table_index: 0,
+                        slice: None,
+                    });
+
+                    current_line_number += 1;
+                }
+                Command::SET => {
+                    let value = match self.line_item_as_value(&current_line.items[2]) {
+                        Ok(v) => v.clone(),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+
+                    self.assign_variable(match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot assign to such variable.".to_string())),
+                    }, value);
+
+                    current_line_number += 1;
+                }
+                Command::TABLE => {
+                    let dimensions = match self.line_items_as_indices(&current_line.items[2..]) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, format!("Invalid dimension. {}", e))),
+                    };
+
+                    let cells = match VM::cell_total(&dimensions) {
+                        Ok(cells) => cells,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
+                    };
+
+                    if let Some(message) = self.check_table_cells(cells) {
+                        return Err(RuntimeError::limit_exceeded(current_line.line_number, message));
+                    }
+
+                    self.create_table(match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot assign to such variable.".to_string())),
+                    }, &dimensions);
+                    current_line_number += 1;
+                },
+                Command::GET => {
+                    let variable_name = match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot read such variable.".to_string())),
+                    };
+                    let table = match self.read_variable(variable_name) {
+                        Some(v) => match v.value_type {
+                            ValueType::Table | ValueType::Dictionary | ValueType::List => v,
+                            _ => return Err(RuntimeError::new(current_line.line_number, "Variable is not a table.".to_string())),
+                        },
+                        None => return Err(RuntimeError::new(current_line.line_number, format!("Variable {} not found.", variable_name))),
+                    }.to_owned();
+                    if !matches!(table.value_type, ValueType::Table) {
+                        let value = match VM::argument(current_line, 2).and_then(|key| self.container_get(&table, key)) {
                             Ok(v) => v,
-                            Err(e) => panic!("Error at line {}: Error reading from table. {}", current_line.line_number, e)
-                        };
-                        let dimensions = current_line.items[2..(2 + table_dimensions.len())].iter().map(
-                            |item| match self.line_item_as_value_pure(item) {
-                                Ok(value) => match value.value_type {
-                                    ValueType::Number => value.number_value.round() as usize,
-                                    _ => panic!("Error at line {}: Invalid dimension. Each dimension must be a number.", current_line.line_number)
-                                },
-                                Err(e) => panic!("Error at line {}: Invalid dimension. {}", current_line.line_number, e),
-                            }
-                        ).collect::<Vec<usize>>();
-                        let value = match self.line_item_as_value(&current_line.items[2 + table_dimensions.len()]) {
-                            Ok(v) => v.clone(),
-                            Err(e) => panic!("Error at line {}: {}", current_line.line_number, e),
-                        };
-                        match self.table_manager.put(&table, dimensions, value) {
-                            Ok(()) => (),
-                            Err(e) => panic!("Error at line {}: Error writing to table. {}", current_line.line_number, e),
+                            Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
                         };
+                        self.assign_variable(&"@save".to_string(), value);
                         current_line_number += 1;
-                    },
-                    Command::SLICE => {
-                        let variable_name = match &current_line.items[1] {
-                            LineItem::Label(label) => label,
-                            _ => panic!("Error at line {}: Cannot read such variable.", current_line.line_number),
-                        };
-
-                        let table = match self.read_variable(variable_name) {
-                            Some(v) => match v.value_type {
-                                ValueType::Table => v,
-                                _ => panic!("Error at line {}: Variable is not a table.", current_line.line_number),
+                        self.program_counter = current_line_number;
+                        return Ok(Status::Running);
+                    }
+                    let dimensions = match self.line_items_as_indices(&current_line.items[2..]) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, format!("Invalid dimension. {}", e))),
+                    };
+                    let value = match self.table_manager.get(&table, dimensions) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, format!("Error reading from table. {}", e))),
+                    }.clone();
+                    self.assign_variable(&"@save".to_string(), value);
+                    current_line_number += 1;
+                }
+                Command::PUT => {
+                    let variable_name = match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot read such variable.".to_string())),
+                    };
+                    let table = match self.read_variable(variable_name) {
+                        Some(v) => {match v.value_type {
+                            ValueType::Table | ValueType::Dictionary | ValueType::List => v,
+                            _ => return Err(RuntimeError::new(current_line.line_number, "Variable is not a table.".to_string())),
+                        }},
+                        None => return Err(RuntimeError::new(current_line.line_number, format!("Variable {} not found.", variable_name))),
+                    }.to_owned();
+                    if !matches!(table.value_type, ValueType::Table) {
+                        let arguments = VM::argument(current_line, 2).and_then(|key| Ok((key, VM::argument(current_line, 3)?)));
+                        if let Err(e) = arguments.and_then(|(key, value)| self.container_put(&table, key, value)) {
+                            return Err(RuntimeError::new(current_line.line_number, e.to_string()));
+                        }
+                        current_line_number += 1;
+                        self.program_counter = current_line_number;
+                        return Ok(Status::Running);
+                    }
+                    let table_dimensions = match self.table_manager.get_dimensions(&table) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, format!("Error reading from table. {}", e)))
+                    };
+                    // The indices are either given one by one or as a single index table.
+                    let index_count = match current_line.items.get(2) {
+                        Some(LineItem::Table(_, _)) => 1,
+                        Some(LineItem::Label(name)) if matches!(self.read_variable(name), Some(v) if matches!(v.value_type, ValueType::Table)) => 1,
+                        _ => table_dimensions.len(),
+                    };
+                    if let Err(e) = VM::argument(current_line, 2 + index_count) {
+                        return Err(RuntimeError::new(current_line.line_number, e));
+                    }
+                    let dimensions = match self.line_items_as_indices(&current_line.items[2..(2 + index_count)]) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, format!("Invalid dimension. {}", e))),
+                    };
+                    let value = match self.line_item_as_value(&current_line.items[2 + index_count]) {
+                        Ok(v) => v.clone(),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+                    match self.table_manager.put(&table, dimensions, value) {
+                        Ok(()) => (),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, format!("Error writing to table. {}", e))),
+                    };
+                    current_line_number += 1;
+                },
+                Command::SLICE => {
+                    let variable_name = match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot read such variable.".to_string())),
+                    };
+
+                    let table = match self.read_variable(variable_name) {
+                        Some(v) => match v.value_type {
+                            ValueType::Table => v,
+                            _ => return Err(RuntimeError::new(current_line.line_number, "Variable is not a table.".to_string())),
+                        },
+                        None => return Err(RuntimeError::new(current_line.line_number, format!("Variable {} not found.", variable_name))),
+                    };
+
+                    let n_dimensions = match table.value_type {
+                        ValueType::Table if !table.is_uninitialized() => match self.table_manager.get_dimensions(table) {
+                            Ok(dimensions) => dimensions.len(),
+                            Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                        },
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Value is not a table.".to_string())),
+                    };
+
+                    // Start indices, then optionally exclusive end indices, then optionally strides.
+                    let bounds = current_line.items[2..].iter().map(
+                        |item| match self.line_item_as_value_pure(item) {
+                            Ok(value) => match value.value_type {
+                                ValueType::Number => Ok(value.number_value.round() as usize),
+                                _ => Err(RuntimeError::new(current_line.line_number, "Invalid dimension. Each dimension must be a number.".to_string()))
                             },
-                            None => panic!("Error at line {}: Variable {} not found.", current_line.line_number, variable_name),
-                        };
+                            Err(e) => Err(RuntimeError::new(current_line.line_number, format!("Invalid dimension. {}", e))),
+                        }
+                    ).collect::<Result<Vec<usize>, RuntimeError>>()?;
 
-                        let n_dimensions = match table.value_type {
-                            ValueType::Table if !table.is_uninitialized() => table.slice_offset.as_ref().unwrap().len(),
-                            _ => panic!("Error at line {}: Value is not a table.", current_line.line_number),
-                        };
+                    if ![1, 2, 3].iter().any(|parts| bounds.len() == parts * n_dimensions) {
+                        return Err(RuntimeError::new(current_line.line_number, format!("Slicing a table of {} dimensions takes {} start indices, optionally followed by as many end indices and strides.", n_dimensions, n_dimensions)));
+                    }
 
-                        let offset_to_add = current_line.items[2..2 + n_dimensions].iter().map(
-                            |item| match self.line_item_as_value_pure(item) {
-                                Ok(value) => match value.value_type {
-                                    ValueType::Number => value.number_value.round() as usize,
-                                    _ => panic!("Error at line {}: Invalid dimension. Each dimension must be a number.", current_line.line_number)
-                                },
-                                Err(e) => panic!("Error at line {}: Invalid dimension. {}", current_line.line_number, e),
-                            }
-                        ).collect::<Vec<usize>>();
-
-                        let new_value = match self.slice_table(table, &offset_to_add) {
-                            Ok(v) => v,
-                            Err(err) => panic!("Error at line {}: {}", current_line.line_number, err),
-                        };
+                    let new_value = match self.slice_table(table, &bounds[0..n_dimensions], bounds.get(n_dimensions..2 * n_dimensions), bounds.get(2 * n_dimensions..3 * n_dimensions)) {
+                        Ok(v) => v,
+                        Err(err) => return Err(RuntimeError::new(current_line.line_number, err.to_string())),
+                    };
 
-                        self.assign_variable(&"@save".to_string(), new_value);
+                    self.assign_variable(&"@save".to_string(), new_value);
 
-                        current_line_number += 1;
+                    current_line_number += 1;
+                }
+                Command::DIMENSIONS | Command::SIZE | Command::COPY | Command::DEEPCOPY | Command::FILL | Command::RESHAPE | Command::RESIZE => {
+                    if let Err(e) = self.table_command(command, current_line) {
+                        return Err(RuntimeError::new(current_line.line_number, e.to_string()));
                     }
-                    Command::NOOP => {
-                        current_line_number += 1;
-                    },
+                    current_line_number += 1;
+                },
+                Command::DICTIONARY => {
+                    let value = self.table_manager.create_dictionary();
+                    self.assign_variable(match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot assign to such variable.".to_string())),
+                    }, value);
+                    self.count_table_creation();
+                    current_line_number += 1;
+                },
+                Command::LIST => {
+                    let items = match current_line.items[2..].iter().map(|item| self.line_item_as_value(item)).collect::<Result<Vec<Value>, String>>() {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    };
+                    if let Err(e) = self.reserve_cells(items.len()) {
+                        return Err(RuntimeError::new(current_line.line_number, e));
+                    }
+                    let value = self.table_manager.create_list(items);
+                    self.assign_variable(match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot assign to such variable.".to_string())),
+                    }, value);
+                    self.count_table_creation();
+                    current_line_number += 1;
+                },
+                Command::PUSH | Command::POP | Command::PUSHFRONT | Command::POPFRONT | Command::INSERT | Command::REMOVE | Command::SORT => {
+                    let result = self.line_item_as_value(&current_line.items[1])
+                        .and_then(|list| self.list_command(command, &list, current_line));
+                    if let Err(e) = result {
+                        return Err(RuntimeError::new(current_line.line_number, e.to_string()));
+                    }
+                    current_line_number += 1;
+                },
+                Command::DELETE | Command::HAS | Command::KEYS => {
+                    let result = self.line_item_as_value(&current_line.items[1])
+                        .and_then(|dictionary| self.dictionary_command(command, &dictionary, current_line));
+                    if let Err(e) = result {
+                        return Err(RuntimeError::new(current_line.line_number, e.to_string()));
+                    }
+                    current_line_number += 1;
+                },
+                Command::MATH => {
+                    let function = match &current_line.items[1] {
+                        LineItem::Label(name) | LineItem::String(name) => name,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "The math function must be a name.".to_string())),
+                    };
+                    let result = current_line.items[2..].iter()
+                        .map(|item| self.line_item_as_value(item))
+                        .collect::<Result<Vec<Value>, String>>()
+                        .and_then(|arguments| math::evaluate(function, &arguments));
+                    match result {
+                        Ok(v) => self.assign_variable(&"@save".to_string(), v),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    }
+                    current_line_number += 1;
+                },
+                Command::RPN => {
+                    match self.evaluate_expression(current_line_number, &current_line.items[1], &current_line.items[2..]) {
+                        Ok(v) => self.assign_variable(&"@save".to_string(), v),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    }
+                    current_line_number += 1;
+                },
+                Command::LET => {
+                    let variable_name = match &current_line.items[1] {
+                        LineItem::Label(label) => label,
+                        _ => return Err(RuntimeError::new(current_line.line_number, "Cannot assign to such variable.".to_string())),
+                    };
+                    let value = match &current_line.items[2] {
+                        LineItem::String(_) => self.evaluate_expression(current_line_number, &current_line.items[2], &current_line.items[3..]),
+                        _ if current_line.items.len() > 3 => Err("Only expressions given as strings take arguments.".to_string()),
+                        item => self.line_item_as_value(item),
+                    };
+                    match value {
+                        Ok(v) => self.assign_variable(variable_name, v),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e.to_string())),
+                    }
+                    current_line_number += 1;
+                },
+                Command::RANDOM => {
+                    if let Err(e) = self.random_command(current_line) {
+                        return Err(RuntimeError::new(current_line.line_number, e.to_string()));
+                    }
+                    current_line_number += 1;
+                },
+                Command::EXIT => {
+                    let value = match current_line.items.get(1) {
+                        Some(item) => match self.line_item_as_value(item) {
+                            Ok(v) => v,
+                            Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
+                        },
+                        None => return Ok(Status::Finished(VM::implicit_exit_code(self.read_variable(&"@save".to_string())))),
+                    };
+                    return match VM::exit_code(Some(&value)) {
+                        Ok(code) => Ok(Status::Finished(code)),
+                        Err(e) => Err(RuntimeError::new(current_line.line_number, e)),
+                    };
+                },
+                Command::ENV => {
+                    let value = match self.environment_variable(current_line) {
+                        Ok(v) => v,
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
+                    };
+                    self.assign_variable(&"@save".to_string(), value);
+                    current_line_number += 1;
+                },
+                Command::FILE => {
+                    if let Err(e) = self.file_command(current_line) {
+                        return Err(RuntimeError::new(current_line.line_number, e));
+                    }
+                    current_line_number += 1;
                 },
-                _ => panic!("Line parsing error: The first item in the line is supposed to be a command."),
+                Command::ASSERT => {
+                    match self.assert_command(current_line) {
+                        Ok(None) => (),
+                        Ok(Some(message)) => return Err(RuntimeError::assertion_failed(current_line.line_number, message)),
+                        Err(e) => return Err(RuntimeError::new(current_line.line_number, e)),
+                    }
+                    current_line_number += 1;
+                },
+                Command::NOOP => {
+                    current_line_number += 1;
+                },
+            },
+            _ => return Err(RuntimeError::new(current_line.line_number, "Line parsing error: The first item in the line is supposed to be a command.".to_string())),
+        }
+        self.program_counter = current_line_number;
+        Ok(Status::Running)
+    }
+
+    /// Counts the line about to run and checks every limit, describing the one that is exceeded.
+    fn check_limits(&mut self) -> Option<String> {
+        self.executed_lines += 1;
+
+        if let Some(max) = self.limits.max_lines {
+            if self.executed_lines > max {
+                return Some(format!("The program ran more than {} lines.", max));
+            }
+        }
+
+        if let Some(max) = self.limits.max_time {
+            if self.started.elapsed() > max {
+                return Some(format!("The program ran longer than {} seconds.", max.as_secs_f64()));
+            }
+        }
+
+        if let Some(max) = self.limits.max_call_depth {
+            if self.environment.len() - 1 > max {
+                return Some(format!("The program made more than {} nested calls.", max));
             }
         }
+
+        self.check_table_cells(0)
+    }
+
+    /// Checks there is room for more table cells, collecting garbage first if there isn't.
+    fn check_table_cells(&mut self, new_cells: usize) -> Option<String> {
+        let max = self.limits.max_table_cells?;
+
+        if self.table_manager.cell_count().saturating_add(new_cells) > max {
+            self.collect_garbage();
+            if self.table_manager.cell_count().saturating_add(new_cells) > max {
+                return Some(format!("The tables of the program would have more than {} cells.", max));
+            }
+        }
+
+        None
+    }
+
+    /// Checks there is room for more cells before they are made, for commands that can't stop
+    /// the program with a limit error themselves.
+    fn reserve_cells(&mut self, new_cells: usize) -> Result<(), String> {
+        match self.check_table_cells(new_cells) {
+            Some(message) => {
+                self.exceeded_limit = Some(message.clone());
+                Err(message)
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// The item at the index of the line, or an error if the command is missing it.
+    fn argument(line: &Line, index: usize) -> Result<&LineItem, String> {
+        line.items.get(index).ok_or_else(|| match line.items.first() {
+            Some(LineItem::Command(command)) => format!("{} is missing an argument.", command.name()),
+            _ => "The line is missing an argument.".to_string(),
+        })
+    }
+
+    /// The number of cells of a table with the dimensions.
+    fn cell_total(dimensions: &[usize]) -> Result<usize, String> {
+        dimensions.iter().try_fold(1usize, |total, dimension| total.checked_mul(*dimension))
+            .ok_or_else(|| "The table would have too many cells.".to_string())
+    }
+
+    /// The exit status of a value left in @save, which was never meant to be one as often as not,
+    /// so anything that can't be an exit status counts as success instead of being an error.
+    fn implicit_exit_code(value: Option<&Value>) -> i32 {
+        VM::exit_code(value).unwrap_or(0)
+    }
+
+    /// Numbers are exit statuses, anything else counts as success. Only whole numbers from 0 to
+    /// 255 can be exit statuses.
+    fn exit_code(value: Option<&Value>) -> Result<i32, String> {
+        let v = match value {
+            Some(v) if matches!(v.value_type, ValueType::Number) => v,
+            _ => return Ok(0),
+        };
+        let code = match &v.integer_value {
+            Some(n) => n.to_u8(),
+            None if v.number_value.fract() == 0.0 && (0.0..=255.0).contains(&v.number_value) => Some(v.number_value as u8),
+            None => None,
+        };
+        code.map(i32::from).ok_or_else(|| format!("The exit code must be a whole number from 0 to 255, not {}.", v.number_to_string()))
     }
 
     pub fn line_item_as_value(&mut self, item: &LineItem) -> Result<Value, String> {
         match item {
             
             LineItem::Table(dimensions, data) => {
-                let value = self.table_manager.create_table(dimensions);
-                let values_to_write: Vec<_> = data.iter().map(|it| self.line_item_as_value(it).unwrap()).collect();
+                let dimensions = self.line_items_as_indices(dimensions)?;
+                let cells = VM::cell_total(&dimensions)?;
+                if data.len() > cells {
+                    return Err(format!("{} values are given for a table with room for {}.", data.len(), cells));
+                }
+                self.reserve_cells(cells)?;
+                let value = self.table_manager.create_table(&dimensions);
+                let values_to_write = data.iter().map(|it| self.line_item_as_value(it)).collect::<Result<Vec<_>, String>>()?;
                 self.table_manager.write_raw(&value, &values_to_write)?;
                 Ok(value)
             },
@@ -414,14 +1063,17 @@ impl VM {
                 string_value: None,
                 table_index: usize::MAX,
                 number_value: number_value.clone(),
-                slice_offset: None,
+                integer_value: None,
+                slice: None,
             }),
+            LineItem::Integer(integer_value) => Ok(Value::integer_value(integer_value.clone())),
             LineItem::String(string_value) => Ok(Value {
                 value_type: super::value::ValueType::String,
                 string_value: Some(string_value.clone()),
                 table_index: usize::MAX,
                 number_value: 0f64,
-                slice_offset: None,
+                integer_value: None,
+                slice: None,
             }),
             LineItem::Label(name) => match self.read_variable(name) {
                 Some(v) => Ok(v.clone()),
@@ -432,24 +1084,163 @@ impl VM {
         }
     }
 
-    pub fn line_item_to_string(&mut self, item: &LineItem, current_line_number: &usize) -> String {
+    /// Evaluates an argument used as an index, dimension or size.
+    pub fn line_item_as_index(&mut self, item: &LineItem) -> Result<usize, String> {
+        let value = self.line_item_as_value(item)?;
+        match value.value_type {
+            ValueType::Number if value.number_value >= 0.0 => Ok(value.number_value.round() as usize),
+            _ => Err("Invalid dimension. Each dimension must be a non-negative number.".to_string()),
+        }
+    }
+
+    /// Evaluates indices or dimensions given either one by one or as a single one-dimensional table.
+    pub fn line_items_as_indices(&mut self, items: &[LineItem]) -> Result<Vec<usize>, String> {
+        if items.len() == 1 {
+            let value = self.line_item_as_value(&items[0])?;
+            if matches!(value.value_type, ValueType::Table) && !value.is_uninitialized() {
+                if self.table_manager.get_dimensions(&value)?.len() != 1 {
+                    return Err("Index tables must have one dimension.".to_string());
+                }
+
+                return self.table_manager.cell_values(&value)?.iter().map(|cell| match cell.value_type {
+                    ValueType::Number if cell.number_value >= 0.0 => Ok(cell.number_value.round() as usize),
+                    _ => Err("Each cell of an index table must be a non-negative number.".to_string()),
+                }).collect();
+            }
+        }
+
+        items.iter().map(|item| self.line_item_as_index(item)).collect()
+    }
+
+    pub fn line_item_to_string(&mut self, item: &LineItem) -> Result<String, String> {
         match item {
-            LineItem::Number(number_value) => number_value.to_string(),
-            LineItem::String(string_value) => string_value.to_owned(),
+            LineItem::Number(number_value) => Ok(number_value.to_string()),
+            LineItem::Integer(integer_value) => Ok(integer_value.to_string()),
+            LineItem::String(string_value) => Ok(string_value.to_owned()),
             LineItem::Label(_) => {
-                let value = match self.line_item_as_value(&item) {
-                    Ok(v) => v,
-                    Err(e) => panic!("Error at line {}: {}", current_line_number, e),
-                };
+                let value = self.line_item_as_value(item)?;
 
                 match value.value_type {
-                    ValueType::Number => value.number_value.to_string(),
-                    ValueType::String => value.string_value.unwrap(),
-                    ValueType::Table => panic!("Error at line {}: Cannot say a table value.", current_line_number),
+                    ValueType::Number => Ok(value.number_to_string()),
+                    ValueType::String => Ok(value.string_value.unwrap()),
+                    ValueType::Table => Err("Cannot say a table value.".to_string()),
+                    ValueType::Dictionary => Err("Cannot say a dictionary value.".to_string()),
+                    ValueType::List => Err("Cannot say a list value.".to_string()),
                 }
             },
             LineItem::Command(_) => panic!("Unexpected argument of type command."),
-            _ => "".to_string(),
+            _ => Ok("".to_string()),
+        }
+    }
+
+    /// The step of a for line is its optional last argument and can be negative or fractional.
+    pub fn for_step(&mut self, for_line: &Line) -> Result<Value, String> {
+        let step = match for_line.items.get(5) {
+            Some(item) => {
+                let value = self.line_item_as_value(item)?;
+                match value.value_type {
+                    ValueType::Number => value,
+                    _ => return Err("The step of a for loop must be a number.".to_string()),
+                }
+            },
+            None => Value::integer_value(BigInt::from(1)),
+        };
+
+        if step.number_value == 0.0 {
+            return Err("The step of a for loop cannot be zero.".to_string());
+        }
+
+        Ok(step)
+    }
+
+    /// Binds the element and its index at a position of an each loop, or returns false if the
+    /// position is past the end. The container is read again on every step, so items the loop
+    /// body adds after the current one are visited and items it removes are not.
+    fn each_step(&mut self, each_line: &Line, line_index: usize, position: usize) -> Result<bool, String> {
+        let (element_variable, index_variable) = match (&each_line.items[3], &each_line.items[4]) {
+            (LineItem::Label(element), LineItem::Label(index)) => (element, index),
+            _ => return Err("Each loop variables must be labels.".to_string()),
+        };
+
+        let container = self.line_item_as_value(&each_line.items[5])?;
+
+        let current = match container.value_type {
+            ValueType::Table if !container.is_uninitialized() => {
+                let dimensions = self.table_manager.get_dimensions(&container)?;
+                if position < dimensions.iter().product() {
+                    let indices = TableManager::unravel(&dimensions, position);
+                    let element = self.table_manager.get(&container, indices.clone())?.clone();
+                    self.reserve_cells(indices.len())?;
+                    let index = self.table_manager.create_table(&vec![indices.len()]);
+                    self.table_manager.write_raw(&index, &indices.iter().map(|i| Value::integer_value(BigInt::from(*i))).collect())?;
+                    Some((element, index))
+                } else {
+                    None
+                }
+            },
+            ValueType::List => {
+                if position < self.table_manager.list_len(&container)? {
+                    Some((self.table_manager.list_get(&container, position)?.clone(), Value::integer_value(BigInt::from(position))))
+                } else {
+                    None
+                }
+            },
+            ValueType::Dictionary => match self.table_manager.dictionary_key_at(&container, position)? {
+                Some(key) => Some((self.table_manager.dictionary_get(&container, &key)?.clone(), key)),
+                None => None,
+            },
+            _ => return Err("Can only iterate over tables, lists and dictionaries.".to_string()),
+        };
+
+        match current {
+            Some((element, index)) => {
+                self.environment.last_mut().unwrap().iterators.insert(line_index, position + 1);
+                self.assign_variable(element_variable, element);
+                self.assign_variable(index_variable, index);
+                if matches!(container.value_type, ValueType::Table) {
+                    self.count_table_creation();
+                }
+                Ok(true)
+            },
+            None => {
+                self.environment.last_mut().unwrap().iterators.remove(&line_index);
+                Ok(false)
+            },
+        }
+    }
+
+    /// Keeps the each loops over a list or dictionary on the same next item when the one at
+    /// `position` is inserted or removed before it, so no item is skipped or visited twice.
+    fn shift_iterators(&mut self, container: &Value, position: usize, inserted: bool) {
+        for frame in 0..self.environment.len() {
+            let lines: Vec<usize> = self.environment[frame].iterators.keys().copied().collect();
+            for line in lines {
+                let iterated = match self.code[line].items.get(5) {
+                    Some(LineItem::Label(name)) => self.environment[..=frame].iter().rev().find_map(|env| env.variables.get(name)),
+                    _ => None,
+                };
+                if iterated.is_none_or(|v| v.table_index != container.table_index) {
+                    continue;
+                }
+
+                let next = self.environment[frame].iterators.get_mut(&line).unwrap();
+                if position < *next {
+                    if inserted { *next += 1 } else { *next -= 1 }
+                }
+            }
+        }
+    }
+
+    /// The line to jump to after an each loop is done.
+    fn each_end(&self, each_line: &Line, line_index: usize, code_length: usize) -> Result<usize, String> {
+        let label_end_text = match &each_line.items[2] {
+            LineItem::Label(label) => label,
+            _ => return Err("Cannot go to non-label.".to_string()),
+        };
+
+        match self.labels.get_line_number_with_inferred(label_end_text, &line_index) {
+            Some(n) if n < code_length => Ok(n),
+            _ => Err("Label not known.".to_string()),
         }
     }
 
@@ -457,24 +1248,24 @@ impl VM {
         self.labels.insert(name, Label {file, line_number});
     }
 
-    pub fn call_function(&mut self, function_line: &Line, call_line: &Line, file: &String, fun: usize, ret: usize) -> Result<Label, &str> {
+    pub fn call_function(&mut self, function_line: &Line, call_line: &Line, file: &String, fun: usize, ret: usize) -> Result<Label, String> {
         // The number 2 is because both the call and function commands begin with two items: command name and function name
         let num_arguments = function_line.items.len() - 2;
         if num_arguments > call_line.items.len() - 2 {
-            return Err("Not enough arguments are supplied.")
+            return Err("Not enough arguments are supplied.".to_string())
+        }
+        let mut new_env = Environment::that_returns_to(Some(Label { file: file.clone(), line_number: ret + 1}));
+        if let LineItem::Label(name) = &function_line.items[1] {
+            new_env.function = Some(name.clone());
         }
-        let new_env = Environment::that_returns_to(Some(Label { file: file.clone(), line_number: ret + 1}));
         self.environment.push(new_env);
 
         let mut i = 2;
         while i < num_arguments + 2 {
-            let value = match self.line_item_as_value(&call_line.items[i]) {
-                Ok(v) => v,
-                Err(e) => panic!("Error at line {}: {}", ret, e),
-            };
+            let value = self.line_item_as_value(&call_line.items[i])?;
             self.assign_variable(match &function_line.items[i] {
                 LineItem::Label(label) => label,
-                _ => panic!("Error at line {}: Function arguments must be variables.", function_line.line_number),
+                _ => return Err(format!("Function arguments must be variables, see line {}.", function_line.line_number)),
             }, value);
             i += 1;
         }
@@ -482,16 +1273,13 @@ impl VM {
         Ok(Label { file: file.clone(), line_number: fun })
     }
 
-    pub fn return_from_function(&mut self, return_line: &Line, line_number: usize) -> Result<Label, &str> {
+    pub fn return_from_function(&mut self, return_line: &Line) -> Result<Label, String> {
         if self.environment.len() <= 1 {
-            return Err("Cannot return from root.");
+            return Err("Cannot return from root.".to_string());
         }
 
         let return_value = if return_line.items.len() > 1 {
-            match self.line_item_as_value(&return_line.items[1]) {
-                Ok(v) => v,
-                Err(e) => panic!("Error at line {}: {}", line_number, e),
-            }
+            self.line_item_as_value(&return_line.items[1])?
         } else {
             Value::uninitialized()
         };
@@ -508,6 +1296,10 @@ impl VM {
     pub fn assign_variable(&mut self, name: &String, value: Value) {
         let env_index = self.environment.len() - 1;
 
+        if !self.tracers.is_empty() {
+            self.traced_assignments.push((name.clone(), value.clone()));
+        }
+
         self.environment
             .get_mut(env_index).unwrap()
             .variables.insert(name.clone(), value);
@@ -515,8 +1307,11 @@ impl VM {
 
     pub fn read_variable(&self, name: &String) -> Option<&Value> {
         for env in self.environment.iter().rev() {
-            if env.variables.contains_key(name) {
-                return env.variables.get(name);
+            if let Some(value) = env.variables.get(name) {
+                if !self.tracers.is_empty() {
+                    self.traced_reads.borrow_mut().push((name.clone(), value.clone()));
+                }
+                return Some(value);
             }
         }
 
@@ -528,40 +1323,35 @@ impl VM {
         match operand.as_str() {
             "+" => match t {
                 (ValueType::String, ValueType::String) => Ok(Value::string_value(&format!("{}{}", v1.string_value.as_ref().unwrap(), v2.string_value.as_ref().unwrap()))),
-                (ValueType::String, ValueType::Number) => Ok(Value::string_value(&format!("{}{}", v1.string_value.as_ref().unwrap(), v2.number_value))),
-                (ValueType::Number, ValueType::String) => Ok(Value::string_value(&format!("{}{}", v1.number_value, v2.string_value.as_ref().unwrap()))),
-                (ValueType::Number, ValueType::Number) => Ok(Value::number_value(v1.number_value + v2.number_value)),
+                (ValueType::String, ValueType::Number) => Ok(Value::string_value(&format!("{}{}", v1.string_value.as_ref().unwrap(), v2.number_to_string()))),
+                (ValueType::Number, ValueType::String) => Ok(Value::string_value(&format!("{}{}", v1.number_to_string(), v2.string_value.as_ref().unwrap()))),
+                (ValueType::Number, ValueType::Number) => VM::do_number_operation(v1, v2, "+"),
                 _ => Err("Addition with tables not supported."),
             },
             "==" => match t {
                 (ValueType::String, ValueType::String) => Ok(Value::boolean_value(v1.string_value.as_ref().unwrap() == v2.string_value.as_ref().unwrap())),
-                (ValueType::Number, ValueType::Number) => Ok(Value::boolean_value(v1.number_value == v2.number_value)),
+                (ValueType::Number, ValueType::Number) => Ok(Value::boolean_value(v1.compare_numbers(v2) == Some(Ordering::Equal))),
                 _ => Err("Two strings or two numbers can be compared only."),
             }
             "-" | "*" | "/" | "%" => match t {
-                (ValueType::Number, ValueType::Number) => Ok(Value::number_value(match operand.as_str() {
-                    "-" => v1.number_value - v2.number_value,
-                    "*" => v1.number_value * v2.number_value,
-                    "/" => v1.number_value / v2.number_value,
-                    "%" => v1.number_value % v2.number_value,
-                    _ => todo!(),
-                })),
+                (ValueType::Number, ValueType::Number) => VM::do_number_operation(v1, v2, operand),
                 _ => Err("Numerical operation used with non-number arguments."),
             },
             "<" | ">" | "<=" | ">=" => match t {
-                (ValueType::Number, ValueType::Number) => Ok(Value::boolean_value(match operand.as_str() {
-                    "<" => v1.number_value < v2.number_value,
-                    ">" => v1.number_value > v2.number_value,
-                    "<=" => v1.number_value <= v2.number_value,
-                    ">=" => v1.number_value >= v2.number_value,
-                    _ => todo!(),
+                (ValueType::Number, ValueType::Number) => Ok(Value::boolean_value(match (operand.as_str(), v1.compare_numbers(v2)) {
+                    (_, None) => false,
+                    ("<", Some(ordering)) => ordering.is_lt(),
+                    (">", Some(ordering)) => ordering.is_gt(),
+                    ("<=", Some(ordering)) => ordering.is_le(),
+                    (">=", Some(ordering)) => ordering.is_ge(),
+                    _ => return Err("Unsupported operand."),
                 })),
 				(ValueType::String, ValueType::String) => Ok(Value::boolean_value(match operand.as_str() {
 					"<" => v1.string_value.as_ref().unwrap() < v2.string_value.as_ref().unwrap(),
                     ">" => v1.string_value.as_ref().unwrap() > v2.string_value.as_ref().unwrap(),
                     "<=" => v1.string_value.as_ref().unwrap() <= v2.string_value.as_ref().unwrap(),
                     ">=" => v1.string_value.as_ref().unwrap() >= v2.string_value.as_ref().unwrap(),
-                    _ => todo!(),
+                    _ => return Err("Unsupported operand."),
 				})),
                 _ => Err("Numerical operation used with non-number arguments."),
             },
@@ -569,8 +1359,50 @@ impl VM {
         }
     }
 
-    pub fn say(&self, message: String) {
-        print!("{}", message);
+    /// Evaluates an expression string with the given arguments. The expression is parsed
+    /// the first time the line runs.
+    fn evaluate_expression(&mut self, line_index: usize, expression: &LineItem, argument_items: &[LineItem]) -> Result<Value, String> {
+        let arguments = argument_items.iter()
+            .map(|item| self.line_item_as_value(item))
+            .collect::<Result<Vec<Value>, String>>()?;
+
+        let parsed = match self.expressions.entry(line_index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => match expression {
+                LineItem::String(source) => entry.insert(Expression::parse(source)?),
+                _ => return Err("The expression must be a string.".to_string()),
+            },
+        };
+
+        parsed.evaluate(&arguments)
+    }
+
+    /// Integers stay exact unless a division doesn't come out even. Anything involving a float
+    /// is done with floats.
+    fn do_number_operation(v1: &Value, v2: &Value, operand: &str) -> Result<Value, &'static str> {
+        if let (Some(a), Some(b)) = (&v1.integer_value, &v2.integer_value) {
+            match operand {
+                "+" => return Ok(Value::integer_value(a + b)),
+                "-" => return Ok(Value::integer_value(a - b)),
+                "*" => return Ok(Value::integer_value(a * b)),
+                "/" if !b.is_zero() && (a % b).is_zero() => return Ok(Value::integer_value(a / b)),
+                "%" if !b.is_zero() => return Ok(Value::integer_value(a % b)),
+                _ => (),
+            }
+        }
+
+        Ok(Value::number_value(match operand {
+            "+" => v1.number_value + v2.number_value,
+            "-" => v1.number_value - v2.number_value,
+            "*" => v1.number_value * v2.number_value,
+            "/" => v1.number_value / v2.number_value,
+            "%" => v1.number_value % v2.number_value,
+            _ => return Err("Unsupported operand."),
+        }))
+    }
+
+    pub fn say(&mut self, message: String) {
+        self.io.write(&message);
     }
 
     pub fn create_table(&mut self, name: &String, dimensions: &Vec<usize>) {
@@ -578,6 +1410,11 @@ impl VM {
 
         self.assign_variable(name, value);
 
+        self.count_table_creation();
+    }
+
+    /// Collects garbage every few table creations. The new table must already be assigned to a variable.
+    fn count_table_creation(&mut self) {
         self.table_creations += 1;
         if self.table_creations > 20 {
             self.collect_garbage();
@@ -585,28 +1422,548 @@ impl VM {
         }
     }
 
-    pub fn slice_table(&self, table_value: &Value, offset: &Vec<usize>) -> Result<Value, &'static str> {
-        let table_dimensions = self.table_manager.get_dimensions(&table_value)?;
+    /// Commands that query or change a whole table at once.
+    fn table_command(&mut self, command: &Command, line: &Line) -> Result<(), String> {
+        let table = self.line_item_as_value(&line.items[1])?;
+
+        match table.value_type {
+            ValueType::Dictionary => return self.dictionary_command(command, &table, line),
+            ValueType::List => return self.list_command(command, &table, line),
+            ValueType::Table if !table.is_uninitialized() => (),
+            _ => return Err("Value is not a table.".to_string()),
+        }
+
+        match command {
+            Command::DIMENSIONS => {
+                let dimensions = self.table_manager.get_dimensions(&table)?;
+                self.assign_variable(&"@save".to_string(), Value::integer_value(BigInt::from(dimensions.len())));
+            },
+            Command::SIZE => {
+                let dimensions = self.table_manager.get_dimensions(&table)?;
+                let size = match line.items.get(2) {
+                    Some(item) => {
+                        let dimension = self.line_item_as_index(item)?;
+                        *dimensions.get(dimension).ok_or("The table doesn't have such a dimension.")?
+                    },
+                    None => dimensions.iter().product(),
+                };
+                self.assign_variable(&"@save".to_string(), Value::integer_value(BigInt::from(size)));
+            },
+            Command::COPY => {
+                self.reserve_cells(self.table_manager.get_dimensions(&table)?.iter().product())?;
+                let copy = self.table_manager.copy(&table)?;
+                self.assign_variable(&"@save".to_string(), copy);
+                self.count_table_creation();
+            },
+            Command::DEEPCOPY => {
+                self.reserve_cells(self.table_manager.reachable_cells(&table))?;
+                let copy = self.table_manager.deep_copy(&table)?;
+                self.assign_variable(&"@save".to_string(), copy);
+                self.count_table_creation();
+            },
+            Command::FILL => {
+                let value = self.line_item_as_value(&line.items[2])?;
+                self.table_manager.fill(&table, &value)?;
+            },
+            Command::RESHAPE => {
+                let dimensions = line.items[2..].iter()
+                    .map(|item| self.line_item_as_index(item))
+                    .collect::<Result<Vec<usize>, String>>()?;
+                self.table_manager.reshape(&table, &dimensions)?;
+            },
+            Command::RESIZE => {
+                let dimension = self.line_item_as_index(&line.items[2])?;
+                let size = self.line_item_as_index(&line.items[3])?;
+                let mut dimensions = self.table_manager.get_dimensions(&table)?;
+                if dimension < dimensions.len() {
+                    let cells = VM::cell_total(&dimensions)?;
+                    dimensions[dimension] = size;
+                    self.reserve_cells(VM::cell_total(&dimensions)?.saturating_sub(cells))?;
+                }
+                self.table_manager.resize(&table, dimension, size)?;
+            },
+            _ => return Err(format!("{} is not a table command.", command.name())),
+        }
+
+        Ok(())
+    }
+
+    /// Slices with indices relative to the value. Ends default to the end of the table and strides to 1.
+    pub fn slice_table(&self, table_value: &Value, start: &[usize], end: Option<&[usize]>, stride: Option<&[usize]>) -> Result<Value, &'static str> {
+        let current_slice = self.table_manager.get_slice(table_value)?;
+        let ones = vec![1usize; start.len()];
 
-        let new_offset: Vec<usize> = table_value.slice_offset.as_ref().unwrap().iter()
-            // Add the new offset
-            .zip(offset).map(|(a, b)| a + b)
-            // Check if the new offset is below capacity
-            .zip(table_dimensions)
-                .map(|(len, cap)| if &len < cap { Ok(len) } else {Err("Slice start too large.")})
-            .collect::<Result<_, _>>()?;
+        let new_slice = current_slice.slice(
+            start,
+            end.unwrap_or(&current_slice.extent),
+            stride.unwrap_or(&ones),
+        )?;
         
         Ok(Value {
             value_type: ValueType::Table,
             string_value: None,
             number_value: 0f64,
+            integer_value: None,
             table_index: table_value.table_index,
-            slice_offset: Some(new_offset),
+            slice: Some(new_slice),
         })
     }
 
+    /// Reads from a dictionary by key or from a list by index.
+    fn container_get(&mut self, container: &Value, key: &LineItem) -> Result<Value, String> {
+        match container.value_type {
+            ValueType::Dictionary => {
+                let key = self.line_item_as_value(key)?;
+                Ok(self.table_manager.dictionary_get(container, &key)?.clone())
+            },
+            ValueType::List => {
+                let index = self.line_item_as_index(key)?;
+                Ok(self.table_manager.list_get(container, index)?.clone())
+            },
+            _ => Err("Value is not a dictionary or a list.".to_string()),
+        }
+    }
+
+    /// Writes to a dictionary by key or to a list by index.
+    fn container_put(&mut self, container: &Value, key: &LineItem, value: &LineItem) -> Result<(), String> {
+        let value = self.line_item_as_value(value)?;
+        match container.value_type {
+            ValueType::Dictionary => {
+                let key = self.line_item_as_value(key)?;
+                if !self.table_manager.dictionary_contains(container, &key)? {
+                    self.reserve_cells(1)?;
+                }
+                Ok(self.table_manager.dictionary_put(container, &key, value)?)
+            },
+            ValueType::List => {
+                let index = self.line_item_as_index(key)?;
+                Ok(self.table_manager.list_put(container, index, value)?)
+            },
+            _ => Err("Value is not a dictionary or a list.".to_string()),
+        }
+    }
+
+    /// Commands that grow, shrink or reorder a list.
+    fn list_command(&mut self, command: &Command, list: &Value, line: &Line) -> Result<(), String> {
+        if !matches!(list.value_type, ValueType::List) {
+            return Err("Value is not a list.".to_string());
+        }
+
+        match command {
+            Command::SIZE => {
+                let size = self.table_manager.list_len(list)?;
+                self.assign_variable(&"@save".to_string(), Value::integer_value(BigInt::from(size)));
+            },
+            Command::PUSH | Command::PUSHFRONT => {
+                let value = self.line_item_as_value(&line.items[2])?;
+                let index = if matches!(command, Command::PUSH) { self.table_manager.list_len(list)? } else { 0 };
+                self.reserve_cells(1)?;
+                self.table_manager.list_insert(list, index, value)?;
+                self.shift_iterators(list, index, true);
+            },
+            Command::POP | Command::POPFRONT => {
+                let length = self.table_manager.list_len(list)?;
+                if length == 0 {
+                    return Err("Cannot pop from an empty list.".to_string());
+                }
+                let index = if matches!(command, Command::POP) { length - 1 } else { 0 };
+                let value = self.table_manager.list_remove(list, index)?;
+                self.shift_iterators(list, index, false);
+                self.assign_variable(&"@save".to_string(), value);
+            },
+            Command::INSERT => {
+                let index = self.line_item_as_index(&line.items[2])?;
+                let value = self.line_item_as_value(&line.items[3])?;
+                self.reserve_cells(1)?;
+                self.table_manager.list_insert(list, index, value)?;
+                self.shift_iterators(list, index, true);
+            },
+            Command::REMOVE => {
+                let index = self.line_item_as_index(&line.items[2])?;
+                let value = self.table_manager.list_remove(list, index)?;
+                self.shift_iterators(list, index, false);
+                self.assign_variable(&"@save".to_string(), value);
+            },
+            Command::SORT => {
+                let ordering = match line.items.get(2) {
+                    Some(LineItem::Label(name)) | Some(LineItem::String(name)) => Some(name.as_str()),
+                    None => None,
+                    _ => return Err("The ordering must be number or string.".to_string()),
+                };
+                self.table_manager.list_sort(list, ordering)?;
+            },
+            _ => return Err(format!("{} cannot be used with a list.", command.name())),
+        }
+
+        Ok(())
+    }
+
+    /// Commands that work with the keys of a dictionary.
+    fn dictionary_command(&mut self, command: &Command, dictionary: &Value, line: &Line) -> Result<(), String> {
+        if !matches!(dictionary.value_type, ValueType::Dictionary) {
+            return Err("Value is not a dictionary.".to_string());
+        }
+
+        match command {
+            Command::SIZE => {
+                let size = self.table_manager.dictionary_len(dictionary)?;
+                self.assign_variable(&"@save".to_string(), Value::integer_value(BigInt::from(size)));
+            },
+            Command::DELETE => {
+                let key = self.line_item_as_value(VM::argument(line, 2)?)?;
+                match self.table_manager.dictionary_delete(dictionary, &key)? {
+                    Some(position) => self.shift_iterators(dictionary, position, false),
+                    None => return Err("Key is not in the dictionary.".to_string()),
+                }
+            },
+            Command::HAS => {
+                let key = self.line_item_as_value(VM::argument(line, 2)?)?;
+                let contains = self.table_manager.dictionary_contains(dictionary, &key)?;
+                self.assign_variable(&"@save".to_string(), Value::boolean_value(contains));
+            },
+            Command::KEYS => {
+                let keys = self.table_manager.dictionary_keys(dictionary)?;
+                self.reserve_cells(keys.len())?;
+                let table = self.table_manager.create_table(&vec![keys.len()]);
+                self.table_manager.write_raw(&table, &keys)?;
+                self.assign_variable(&"@save".to_string(), table);
+                self.count_table_creation();
+            },
+            _ => return Err(format!("{} cannot be used with a dictionary.", command.name())),
+        }
+
+        Ok(())
+    }
+
+    /// Reads an environment variable, or gives the default value if it is not set.
+    fn environment_variable(&mut self, line: &Line) -> Result<Value, String> {
+        let name = self.line_item_as_value(&line.items[1])?;
+        let name = match name.value_type {
+            ValueType::String => name.string_value.unwrap(),
+            _ => return Err("The name of an environment variable must be a string.".to_string()),
+        };
+
+        match (self.environment_variables.get(&name), line.items.get(2)) {
+            (Some(value), _) => Ok(Value::string_value(value)),
+            (None, Some(default)) => self.line_item_as_value(default),
+            (None, None) => Err(format!("Environment variable {} is not set.", name)),
+        }
+    }
+
+    /// Reading, writing and listing files as allowed by the file system policy.
+    fn file_command(&mut self, line: &Line) -> Result<(), String> {
+        let operation = match &line.items[1] {
+            LineItem::Label(name) | LineItem::String(name) => name.as_str(),
+            _ => return Err("The file operation must be a name.".to_string()),
+        };
+
+        let arguments = line.items[2..].iter()
+            .map(|item| self.line_item_as_value(item))
+            .collect::<Result<Vec<Value>, String>>()?;
+
+        let expected = match operation {
+            "read" | "lines" | "list" | "exists" => 1,
+            "write" | "append" => 2,
+            _ => return Err(format!("Unknown file operation {}. It can be read, lines, write, append, list or exists.", operation)),
+        };
+
+        if arguments.len() != expected {
+            return Err(format!("file {} takes {} arguments but {} were given.", operation, expected, arguments.len()));
+        }
+
+        let path = match arguments[0].value_type {
+            ValueType::String => arguments[0].string_value.as_ref().unwrap(),
+            _ => return Err("The path must be a string.".to_string()),
+        };
+
+        let result = match operation {
+            "read" => Value::string_value(&self.file_system.read(path)?),
+            "lines" | "list" => {
+                let strings = if operation == "lines" { self.file_system.read_lines(path)? } else { self.file_system.list(path)? };
+                self.reserve_cells(strings.len())?;
+                let table = self.table_manager.create_table(&vec![strings.len()]);
+                self.table_manager.write_raw(&table, &strings.iter().map(Value::string_value).collect())?;
+                self.assign_variable(&"@save".to_string(), table);
+                self.count_table_creation();
+                return Ok(());
+            },
+            "exists" => Value::boolean_value(self.file_system.exists(path)?),
+            _ => {
+                let content = match arguments[1].value_type {
+                    ValueType::String => arguments[1].string_value.clone().unwrap(),
+                    ValueType::Number => arguments[1].number_to_string(),
+                    _ => return Err("Only strings and numbers can be written to files.".to_string()),
+                };
+                return self.file_system.write(path, &content, operation == "append");
+            },
+        };
+
+        self.assign_variable(&"@save".to_string(), result);
+        Ok(())
+    }
+
+    /// Compares the value with the expected value, describing both if they aren't equal.
+    fn assert_command(&mut self, line: &Line) -> Result<Option<String>, String> {
+        let value = self.line_item_as_value(&line.items[1])?;
+        let expected = self.line_item_as_value(&line.items[2])?;
+
+        if self.values_equal(&value, &expected, &mut vec!())? {
+            return Ok(None);
+        }
+
+        Ok(Some(format!("got {} but expected {}.", self.describe_value(&value, 0), self.describe_value(&expected, 0))))
+    }
+
+    /// Strings and numbers are equal as with ==, and tables, lists and dictionaries are equal when
+    /// their dimensions or keys are and everything in them is. Values of different types are
+    /// never equal. Containers that contain themselves are taken to be equal when they are
+    /// reached again while being compared.
+    fn values_equal(&self, v1: &Value, v2: &Value, comparing: &mut Vec<(usize, Option<TableSlice>, usize, Option<TableSlice>)>) -> Result<bool, &'static str> {
+        match (&v1.value_type, &v2.value_type) {
+            (ValueType::String, ValueType::String) | (ValueType::Number, ValueType::Number) => return Ok(VM::do_sum(v1, v2, &"==".to_string())?.as_boolean()),
+            (ValueType::Table, ValueType::Table) | (ValueType::Dictionary, ValueType::Dictionary) | (ValueType::List, ValueType::List) => (),
+            _ => return Ok(false),
+        }
+
+        if v1.is_uninitialized() || v2.is_uninitialized() {
+            return Ok(v1.is_uninitialized() && v2.is_uninitialized());
+        }
+
+        let pair = (v1.table_index, v1.slice.clone(), v2.table_index, v2.slice.clone());
+        if comparing.contains(&pair) {
+            return Ok(true);
+        }
+
+        let (items1, items2) = match v1.value_type {
+            ValueType::Table => {
+                if self.table_manager.get_dimensions(v1)? != self.table_manager.get_dimensions(v2)? {
+                    return Ok(false);
+                }
+                (self.table_manager.cell_values(v1)?, self.table_manager.cell_values(v2)?)
+            },
+            ValueType::List => {
+                let len = self.table_manager.list_len(v1)?;
+                if len != self.table_manager.list_len(v2)? {
+                    return Ok(false);
+                }
+                (
+                    (0..len).map(|i| self.table_manager.list_get(v1, i).cloned()).collect::<Result<_, _>>()?,
+                    (0..len).map(|i| self.table_manager.list_get(v2, i).cloned()).collect::<Result<_, _>>()?,
+                )
+            },
+            _ => {
+                let keys = self.table_manager.dictionary_keys(v1)?;
+                if keys.len() != self.table_manager.dictionary_len(v2)? {
+                    return Ok(false);
+                }
+                for key in &keys {
+                    if !self.table_manager.dictionary_contains(v2, key)? {
+                        return Ok(false);
+                    }
+                }
+                (
+                    keys.iter().map(|key| self.table_manager.dictionary_get(v1, key).cloned()).collect::<Result<_, _>>()?,
+                    keys.iter().map(|key| self.table_manager.dictionary_get(v2, key).cloned()).collect::<Result<_, _>>()?,
+                )
+            },
+        };
+
+        comparing.push(pair);
+        for (item1, item2) in items1.iter().zip(&items2) {
+            if !self.values_equal(item1, item2, comparing)? {
+                return Ok(false);
+            }
+        }
+        comparing.pop();
+
+        Ok(true)
+    }
+
+    /// The value written the way it would be in a program. Only the first items of large
+    /// containers and the first levels of nested ones are shown.
+    fn describe_value(&self, value: &Value, depth: usize) -> String {
+        const SHOWN_ITEMS: usize = 20;
+        const SHOWN_DEPTH: usize = 3;
+
+        let items = |values: Vec<Value>| {
+            let mut shown: Vec<String> = values.iter().take(SHOWN_ITEMS).map(|item| self.describe_value(item, depth + 1)).collect();
+            if values.len() > SHOWN_ITEMS {
+                shown.push("...".to_string());
+            }
+            shown.join(" ")
+        };
+
+        match value.value_type {
+            ValueType::Number => value.number_to_string(),
+            ValueType::String => format!("{:?}", value.string_value.as_deref().unwrap_or("")),
+            ValueType::Table if value.is_uninitialized() => "an empty table".to_string(),
+            _ if depth >= SHOWN_DEPTH => "[...]".to_string(),
+            ValueType::Table => match (self.table_manager.get_dimensions(value), self.table_manager.cell_values(value)) {
+                (Ok(dimensions), Ok(cells)) => format!("[{}] [{}]",
+                    dimensions.iter().map(|d| d.to_string()).collect::<Vec<String>>().join(" "),
+                    items(cells)),
+                _ => "a missing table".to_string(),
+            },
+            ValueType::List => match self.table_manager.list_len(value) {
+                Ok(len) => format!("list [{}]", items((0..len).filter_map(|i| self.table_manager.list_get(value, i).ok().cloned()).collect())),
+                Err(_) => "a missing list".to_string(),
+            },
+            ValueType::Dictionary => match self.table_manager.dictionary_keys(value) {
+                Ok(keys) => {
+                    let entries: Vec<String> = keys.iter().take(SHOWN_ITEMS).map(|key| format!("{}: {}",
+                        self.describe_value(key, depth + 1),
+                        self.table_manager.dictionary_get(value, key).map(|item| self.describe_value(item, depth + 1)).unwrap_or_default(),
+                    )).collect();
+                    let more = if keys.len() > SHOWN_ITEMS { ", ..." } else { "" };
+                    format!("dictionary {{{}{}}}", entries.join(", "), more)
+                },
+                Err(_) => "a missing dictionary".to_string(),
+            },
+        }
+    }
+
+    /// Random floats and integers, and shuffling or picking from tables and lists.
+    fn random_command(&mut self, line: &Line) -> Result<(), String> {
+        let kind = match &line.items[1] {
+            LineItem::Label(name) | LineItem::String(name) => name.as_str(),
+            _ => return Err("The kind of random value must be a name.".to_string()),
+        };
+
+        let arguments = line.items[2..].iter()
+            .map(|item| self.line_item_as_value(item))
+            .collect::<Result<Vec<Value>, String>>()?;
+
+        let expected = match kind {
+            "float" => 0,
+            "integer" => 2,
+            "shuffle" | "pick" => 1,
+            _ => return Err(format!("Unknown kind of random value {}. It can be float, integer, shuffle or pick.", kind)),
+        };
+
+        if arguments.len() != expected {
+            return Err(format!("random {} takes {} arguments but {} were given.", kind, expected, arguments.len()));
+        }
+
+        let random = &mut self.random;
+        let mut below = |n: usize| random.below(n as u64) as usize;
+
+        match kind {
+            "float" => {
+                let value = Value::number_value(self.random.next_f64());
+                self.assign_variable(&"@save".to_string(), value);
+            },
+            "integer" => {
+                let (low, high) = match (&arguments[0].integer_value, &arguments[1].integer_value) {
+                    (Some(low), Some(high)) => (low, high),
+                    _ => return Err("The bounds of a random integer must be integers.".to_string()),
+                };
+                let span = (high - low + 1u32).to_biguint().filter(|span| !span.is_zero())
+                    .ok_or("The lower bound of a random integer can't be above the upper bound.")?;
+                let value = Value::integer_value(low + BigInt::from(self.random.below_big(&span)));
+                self.assign_variable(&"@save".to_string(), value);
+            },
+            "shuffle" => match arguments[0].value_type {
+                ValueType::Table if !arguments[0].is_uninitialized() => self.table_manager.shuffle(&arguments[0], &mut below)?,
+                ValueType::List => self.table_manager.list_shuffle(&arguments[0], &mut below)?,
+                _ => return Err("Only tables and lists can be shuffled.".to_string()),
+            },
+            _ => {
+                let container = &arguments[0];
+                let value = match container.value_type {
+                    ValueType::Table if !container.is_uninitialized() => {
+                        let cells = self.table_manager.cell_values(container)?;
+                        if cells.is_empty() { None } else { Some(cells[below(cells.len())].clone()) }
+                    },
+                    ValueType::List => match self.table_manager.list_len(container)? {
+                        0 => None,
+                        length => Some(self.table_manager.list_get(container, below(length))?.clone()),
+                    },
+                    ValueType::Dictionary => match self.table_manager.dictionary_len(container)? {
+                        0 => None,
+                        length => self.table_manager.dictionary_key_at(container, below(length))?,
+                    },
+                    _ => return Err("Can only pick from tables, lists and dictionaries.".to_string()),
+                };
+                let value = value.ok_or("Cannot pick from an empty container.")?;
+                self.assign_variable(&"@save".to_string(), value);
+            },
+        }
+
+        Ok(())
+    }
+
     pub fn collect_garbage(&mut self) {
+        let started = Instant::now();
         self.table_manager.mark(&self.environment);
         self.table_manager.sweep();
+        self.garbage_collection_time += started.elapsed();
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kodit::io_backend::MemoryIo;
+    use crate::kodit::{lexer, lexing_specification, line};
+
+    fn output_of(source: &str) -> String {
+        let lines: Vec<String> = source.lines().map(|line| line.to_string()).collect();
+        let specs = lexing_specification::load(&[]).unwrap();
+        let code = lexer::lex(&line::decompose_lines(&lines).unwrap(), &specs).unwrap();
+
+        let io = MemoryIo::new();
+        let mut vm = VM::new();
+        vm.io = Box::new(io.clone());
+        vm.load(&"program.kdt".to_string(), code).unwrap();
+        assert_eq!(vm.run_to_end().unwrap(), 0);
+        io.take_output()
+    }
+
+    #[test]
+    fn removing_the_current_item_of_a_list_skips_no_items() {
+        let output = output_of(r#"list l 1 2 3 4
+each items items_end item i l
+say item
+say " "
+remove l i
+continue items
+label items_end
+size l
+say @save
+set @save 0
+"#);
+        assert_eq!(output, "1 2 3 4 0");
+    }
+
+    #[test]
+    fn deleting_the_current_key_of_a_dictionary_skips_no_keys() {
+        let output = output_of(r#"dictionary d
+put d "a" 1
+put d "b" 2
+put d "c" 3
+each keys keys_end value key d
+say key
+delete d key
+continue keys
+label keys_end
+size d
+say @save
+set @save 0
+"#);
+        assert_eq!(output, "abc0");
+    }
+
+    #[test]
+    fn items_inserted_before_the_current_one_are_not_visited_again() {
+        let output = output_of(r#"list l 1 2 3
+each items items_end item i l
+say item
+pushfront l 0
+continue items
+label items_end
+set @save 0
+"#);
+        assert_eq!(output, "123");
+    }
+}
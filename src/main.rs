@@ -1,48 +1,395 @@
-use std::{fs::File, io::{self, BufRead}};
+use std::{fs::{self, File}, io::{self, Write}, path::{Path, PathBuf}, time::Duration};
 
-use clap::Parser;
-use kodit::lexing_specification::{v0::LexingSpecificationV0, LexingSpecification};
+use clap::{Parser, Subcommand, ValueEnum};
+use kodit::coverage::Coverage;
+use kodit::file_system::FileSystemPolicy;
+use kodit::io_backend::MemoryIo;
+use kodit::limits::Limits;
+use kodit::profiler::Profiler;
+use kodit::trace::{JsonTracer, TextTracer, TraceFilter, TraceHook};
+use kodit::vm::{RuntimeError, Status, VM};
+use kodit::lexing_specification::{v0::LexingSpecificationV0, validation};
 
 mod kodit;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     #[arg(short, long)]
     lexer: Vec<String>,
 
+    /// Seed for the random command so runs can be repeated
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Environment variable for the program, overriding the one of the process
+    #[arg(long = "env", value_name = "NAME=VALUE")]
+    environment_variables: Vec<String>,
+
+    /// Don't let the program access files
+    #[arg(long)]
+    no_files: bool,
+
+    /// Only let the program read files, not write them
+    #[arg(long)]
+    read_only: bool,
+
+    /// Only let the program access files in this directory instead of the working directory, can be given more than once
+    #[arg(long, value_name = "DIRECTORY")]
+    allow_dir: Vec<PathBuf>,
+
+    /// Let the program access files anywhere instead of only in the working directory
+    #[arg(long, conflicts_with = "allow_dir")]
+    all_files: bool,
+
+    /// Stop the program after running this many lines
+    #[arg(long, value_name = "LINES")]
+    max_lines: Option<u64>,
+
+    /// Stop the program after running this long
+    #[arg(long, value_name = "SECONDS")]
+    max_time: Option<f64>,
+
+    /// Stop the program when it makes more nested calls than this
+    #[arg(long, value_name = "CALLS")]
+    max_call_depth: Option<usize>,
+
+    /// Stop the program when its tables would have more cells than this
+    #[arg(long, value_name = "CELLS")]
+    max_table_cells: Option<usize>,
+
+    /// Read the input of ask lines from this file instead of the standard input
+    #[arg(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// Pause the program after running this many lines and save it to the snapshot file
+    #[arg(long, value_name = "LINES", requires = "snapshot")]
+    steps: Option<u64>,
+
+    /// Where to save the program when it is paused
+    #[arg(long, value_name = "FILE", requires = "steps")]
+    snapshot: Option<PathBuf>,
+
+    /// Continue a program from a snapshot taken with the same program
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+
+    /// Write every line that runs with the variables it reads and sets, as text or JSON lines
+    #[arg(long, value_name = "FORMAT")]
+    trace: Option<TraceFormat>,
+
+    /// Where to write the trace instead of the standard error
+    #[arg(long, value_name = "FILE", requires = "trace")]
+    trace_output: Option<PathBuf>,
+
+    /// Only trace the lines after this label, for, each or function line, can be given more than once
+    #[arg(long, value_name = "LABEL", requires = "trace")]
+    trace_label: Vec<String>,
+
+    /// Only trace the lines run inside calls to this function, can be given more than once
+    #[arg(long, value_name = "FUNCTION", requires = "trace")]
+    trace_function: Vec<String>,
+
+    /// Count how often and how long every line and function runs and report the slowest
+    #[arg(long)]
+    profile: bool,
+
+    /// Where to write the profile instead of the standard error
+    #[arg(long, value_name = "FILE", requires = "profile")]
+    profile_output: Option<PathBuf>,
+
+    /// Write the time spent in every stack of calls in the folded format of flame graph tools
+    #[arg(long, value_name = "FILE", requires = "profile")]
+    folded_stacks: Option<PathBuf>,
+
+    /// Write which lines and branches of if and for lines ran to this file as an LCOV report
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+
     file_name: Option<String>,
+
+    /// Arguments given to the program in @args
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    arguments: Vec<String>,
+}
+
+#[derive(Clone, ValueEnum)]
+enum TraceFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, ValueEnum)]
+enum DocFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Check lexing specifications for missing commands, ambiguous formats and argument numbering
+    SpecCheck {
+        file_names: Vec<String>,
+    },
+    /// Run every program in a directory and compare its output with the .out file next to it
+    Test {
+        directory: PathBuf,
+
+        /// Save what the programs do as the expected results
+        #[arg(long)]
+        bless: bool,
+    },
+    /// Write a reference page of the functions in the files from their /// comments
+    Doc {
+        file_names: Vec<String>,
+
+        #[arg(short, long)]
+        lexer: Vec<String>,
+
+        #[arg(long, value_name = "FORMAT", default_value = "markdown")]
+        format: DocFormat,
+
+        /// Where to write the page instead of the standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
 }
 
 fn main() {
     let args = Cli::parse();
 
+    match &args.command {
+        Some(Commands::SpecCheck { file_names }) => {
+            spec_check(file_names);
+            return;
+        },
+        Some(Commands::Test { directory, bless }) => {
+            let passed = kodit::test_runner::run_tests(directory, *bless);
+            std::process::exit(if passed { 0 } else { 1 });
+        },
+        Some(Commands::Doc { file_names, lexer, format, output }) => {
+            if let Err(e) = doc(file_names, lexer, format, output.as_deref()) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        },
+        None => (),
+    }
+
     if args.file_name.is_none() {
-        println!("Usage: kodit [--lex <lexing file name>] <entry file name>");
+        println!("Usage: kodit [--lex <lexing file name>] <entry file name> [arguments]");
     }
 
-    let mut lexing_specification : Vec<Box<dyn LexingSpecification>> = args.lexer.iter().map(|file_name| {
-        Box::new(LexingSpecificationV0::from_file(file_name).unwrap()) as _
-    }).collect();
+    let path = args.file_name.as_ref().unwrap();
+
+    let mut vm = VM::new();
 
-    lexing_specification.push(
-        Box::new(LexingSpecificationV0::from_file("lexing-specifications/en.yml").unwrap())
-    );
+    if let Some(seed) = args.seed {
+        vm.seed_random(seed);
+    }
 
-    let path = args.file_name.as_ref().unwrap();
+    vm.set_arguments(&args.arguments);
 
-    let file = File::open(path).unwrap();
+    vm.file_system = if args.no_files {
+        FileSystemPolicy::disabled()
+    } else if args.all_files {
+        FileSystemPolicy::unrestricted()
+    } else if args.allow_dir.is_empty() {
+        FileSystemPolicy::restricted_to(vec!(PathBuf::from(".")))
+    } else {
+        FileSystemPolicy::restricted_to(args.allow_dir.clone())
+    };
+    vm.file_system.read_only |= args.read_only;
 
-    let lines = io::BufReader::new(file).lines();
+    vm.limits = Limits {
+        max_lines: args.max_lines,
+        max_time: args.max_time.map(|seconds| Duration::try_from_secs_f64(seconds).unwrap_or(Duration::ZERO)),
+        max_call_depth: args.max_call_depth,
+        max_table_cells: args.max_table_cells,
+    };
 
-    let mut vm = kodit::vm::VM::new();
+    for variable in &args.environment_variables {
+        let (name, value) = variable.split_once('=').unwrap_or((variable, ""));
+        vm.set_environment_variable(name, value);
+    }
 
-    let lines: Vec<String> = lines.map(|line| {line.unwrap()}).collect();
+    let code = match kodit::lexing_specification::load(&args.lexer).and_then(|specs| kodit::lexer::lex_file(path, &specs)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
 
-    // We don't remove empty lines because possible debugging would require the exact line number.
-    let raw_lines = kodit::line::decompose_lines(&lines).unwrap();
+    if let Some(format) = &args.trace {
+        let output = create_output(args.trace_output.as_deref());
+        let filter = TraceFilter { labels: args.trace_label.clone(), functions: args.trace_function.clone() };
 
-    let code = kodit::lexer::lex(&raw_lines, &lexing_specification).unwrap();
+        vm.tracers.push(match format {
+            TraceFormat::Text => Box::new(TextTracer::new(output, filter)) as Box<dyn TraceHook>,
+            TraceFormat::Json => Box::new(JsonTracer::new(output, filter)),
+        });
+    }
 
-    vm.evaluate(path, &code);
+    if args.profile {
+        vm.tracers.push(Box::new(Profiler::new(create_output(args.profile_output.as_deref()), args.folded_stacks.clone())));
+    }
+
+    if let Some(report) = &args.coverage {
+        vm.tracers.push(Box::new(Coverage::new(path, &code, report.clone())));
+    }
+
+    let io = args.input.as_deref().map(memory_io_with_input);
+    if let Some(io) = &io {
+        vm.io = Box::new(io.clone());
+    }
+
+    let result = if io.is_none() && args.resume.is_none() && args.steps.is_none() {
+        vm.evaluate(path, code).map(Some)
+    } else {
+        let loaded = match &args.resume {
+            Some(snapshot) => fs::read_to_string(snapshot)
+                .map_err(|e| format!("Cannot read {}: {}", snapshot.display(), e))
+                .and_then(|text| kodit::snapshot::restore(&mut vm, path, code, &text)),
+            None => vm.load(path, code).map_err(|e| e.to_string()),
+        };
+
+        if let Err(e) = loaded {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+
+        run(&mut vm, args.steps, io.as_ref())
+    };
+
+    let result = match result {
+        Ok(Some(status)) => Ok(status),
+        Ok(None) => {
+            let snapshot = args.snapshot.as_ref().unwrap();
+            let saved = kodit::snapshot::save(&vm)
+                .and_then(|text| fs::write(snapshot, text).map_err(|e| format!("Cannot write {}: {}", snapshot.display(), e)));
+            if let Err(e) = saved {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            Ok(0)
+        },
+        Err(e) => Err(e),
+    };
+
+    for tracer in &mut vm.tracers {
+        tracer.finish();
+    }
+
+    match result {
+        Ok(status) => std::process::exit(status),
+        Err(e) => {
+            io::stdout().flush().unwrap();
+            eprintln!("{}", e);
+            std::process::exit(e.exit_code());
+        },
+    }
+}
+
+/// A buffered file, or the standard error if no file is given.
+fn create_output(file_name: Option<&Path>) -> Box<dyn Write> {
+    match file_name {
+        Some(file_name) => match File::create(file_name) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("Cannot write {}: {}", file_name.display(), e);
+                std::process::exit(1);
+            },
+        },
+        None => Box::new(io::stderr()),
+    }
+}
+
+/// An in-memory IO backend with the lines of a file as its input.
+fn memory_io_with_input(input: &Path) -> MemoryIo {
+    let io = MemoryIo::new();
+
+    let input = fs::read_to_string(input).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {}", input.display(), e);
+        std::process::exit(1);
+    });
+
+    for line in input.lines() {
+        io.push_input(line);
+    }
+
+    io
+}
+
+/// Runs the loaded program until it ends or has run the given number of lines, printing the
+/// output of an in-memory IO backend as it goes. Gives no exit code if the program was paused.
+fn run(vm: &mut VM, steps: Option<u64>, io: Option<&MemoryIo>) -> Result<Option<i32>, RuntimeError> {
+    let mut remaining = steps.unwrap_or(u64::MAX);
+
+    loop {
+        let chunk = remaining.min(10000);
+        let status = vm.run_for(chunk);
+        remaining -= chunk;
+
+        if let Some(io) = io {
+            print!("{}", io.take_output());
+        }
+
+        match status {
+            Status::Running if remaining == 0 => return Ok(None),
+            Status::Running => (),
+            Status::WaitingForInput => return Err(RuntimeError::new(vm.source_line_number(), "The input file has no more lines.".to_string())),
+            Status::Finished(exit_code) => return Ok(Some(exit_code)),
+            Status::Error(e) => return Err(e),
+        }
+    }
+}
+
+fn spec_check(file_names: &[String]) {
+    let mut has_problems = false;
+
+    for file_name in file_names {
+        let spec = match LexingSpecificationV0::from_file(file_name) {
+            Ok(spec) => spec,
+            Err(e) => {
+                println!("{}", e);
+                has_problems = true;
+                continue;
+            },
+        };
+
+        let report = validation::validate(&spec);
+        print!("{}", report);
+        has_problems |= report.has_problems();
+    }
+
+    if has_problems {
+        std::process::exit(1);
+    }
+}
+
+/// Documents the functions of every file, which can be the files of one program.
+fn doc(file_names: &[String], lexer: &[String], format: &DocFormat, output: Option<&Path>) -> Result<(), String> {
+    let specs = kodit::lexing_specification::load(lexer)?;
+
+    let files = file_names.iter()
+        .map(|file_name| kodit::lexer::lex_file(file_name, &specs).map(|code| kodit::doc::document(file_name, &code)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let page = match format {
+        DocFormat::Markdown => kodit::doc::markdown(&files),
+        DocFormat::Html => kodit::doc::html(&files),
+    };
+
+    match output {
+        Some(path) => fs::write(path, page).map_err(|e| format!("Cannot write {}: {}", path.display(), e)),
+        None => {
+            print!("{}", page);
+            Ok(())
+        },
+    }
 }